@@ -40,7 +40,7 @@ impl Handler<Ping> for Pinger {
 
     type Result = u64;
 
-    async fn handle(&mut self, msg: Ping, ctx: &mut Self::Context) -> Self::Result {
+    async fn handle(&mut self, msg: Ping, _ctx: &mut Self::Context) -> Self::Result {
         let result = self.ponger.send(msg).await;
         result.unwrap() + 1
     }
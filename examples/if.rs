@@ -2,7 +2,6 @@ use std::time::Duration;
 
 use actix::{Actor, Addr, Context, Handler, MailboxError, Message};
 use actix::clock::sleep;
-use actix::fut::result;
 
 use actix_async_handler::async_handler;
 
@@ -41,7 +40,7 @@ impl Handler<Ping> for Pinger {
 
     type Result = u64;
 
-    async fn handle(&mut self, msg: Ping, ctx: &mut Self::Context) -> Self::Result {
+    async fn handle(&mut self, msg: Ping, _ctx: &mut Self::Context) -> Self::Result {
 
         if msg.0 > 1 {
             println!("test code before await");
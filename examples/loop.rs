@@ -1,7 +1,6 @@
 use std::time::Duration;
 
-use actix::{Actor, ActorStreamExt, Addr, Context, Handler, Message};
-use actix::fut::result;
+use actix::{Actor, Addr, Context, Handler, Message};
 use actix_rt::time::sleep;
 
 use actix_async_handler::async_handler;
@@ -43,7 +42,7 @@ impl Handler<Ping> for Pinger {
 
     type Result = u64;
 
-    async fn handle(&mut self, msg: Ping, ctx: &mut Self::Context) -> Self::Result {
+    async fn handle(&mut self, msg: Ping, _ctx: &mut Self::Context) -> Self::Result {
 
         // just looping
         for ponger in self.pongers.clone() {
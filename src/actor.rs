@@ -0,0 +1,165 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Error, FnArg, ImplItem, ImplItemFn, ItemImpl, Result};
+use syn::spanned::Spanned;
+
+use crate::args::{expand_with_args, HandlerArgs};
+use crate::r#impl::{ctx_future, internal_ident, translated_block, Translation};
+
+pub fn async_actor_impl(attribute: TokenStream, input: TokenStream) -> TokenStream {
+    expand_with_args(attribute, |args| async_actor_inner(args, input)
+        .unwrap_or_else(|e| e.to_compile_error()))
+}
+
+// The async lifecycle methods of an Actor or Supervised impl run their futures with `ctx.wait`, so the actor
+// doesn't handle any message until they're done
+pub fn async_actor_inner(args: &HandlerArgs, input: TokenStream) -> Result<TokenStream> {
+    let mut item_impl = syn::parse2::<ItemImpl>(input)?;
+
+    let is_actor = item_impl.trait_.as_ref()
+        .and_then(|trait_| trait_.1.segments.last())
+        .map(|last| last.ident == "Actor" || last.ident == "Supervised")
+        .unwrap_or(false);
+    if !is_actor {
+        return Err(Error::new(item_impl.span(), "#[async_actor] can only be applied to an Actor or Supervised impl"))
+    }
+
+    for item in &mut item_impl.items {
+        let ImplItem::Fn(method) = item else {
+            continue
+        };
+        if method.sig.asyncness.is_none() {
+            continue
+        }
+        match method.sig.ident.to_string().as_str() {
+            "started" | "restarting" => waited_hook(args, &Translation::default(), method, "#[async_actor]")?,
+            "stopping" => stopping_hook(args, method)?,
+            "stopped" => return Err(Error::new(method.sig.span(),
+                "#[async_actor] `stopped` can't be async, there's no actor left to run its future")),
+            _ => {}
+        }
+    }
+
+    Ok(quote! { #item_impl })
+}
+
+// Each item is handled with `ctx.wait` as well, so the next one waits for it unless `non_atomic`, which
+// handles them concurrently with `ctx.spawn`
+pub fn stream_handler_inner(args: &HandlerArgs, translation: &Translation, mut item_impl: ItemImpl) -> Result<TokenStream> {
+    for item in &mut item_impl.items {
+        let ImplItem::Fn(method) = item else {
+            continue
+        };
+        if method.sig.asyncness.is_none() {
+            continue
+        }
+        match method.sig.ident.to_string().as_str() {
+            "handle" if !args.is_atomic => spawned_hook(args, translation, method)?,
+            "handle" | "started" | "finished" => waited_hook(args, translation, method, "#[async_handler]")?,
+            _ => {}
+        }
+    }
+
+    Ok(quote! { #item_impl })
+}
+
+// `started(&mut self, ctx)` and alike, returning once their future is scheduled with `ctx.wait`
+fn waited_hook(args: &HandlerArgs, translation: &Translation, method: &mut ImplItemFn, attr: &str) -> Result<()> {
+    let future = hook_future(args, translation, method, attr)?;
+    let krate = &args.krate;
+    let ctx = internal_ident("__ctx");
+    method.block = translated_block(quote!({
+        use #krate::{ActorFutureExt, AsyncContext};
+        #ctx.wait(#future);
+    }), method.span())?;
+    Ok(())
+}
+
+fn spawned_hook(args: &HandlerArgs, translation: &Translation, method: &mut ImplItemFn) -> Result<()> {
+    let future = hook_future(args, translation, method, "#[async_handler]")?;
+    let krate = &args.krate;
+    let ctx = internal_ident("__ctx");
+    method.block = translated_block(quote!({
+        use #krate::{ActorFutureExt, AsyncContext};
+        #ctx.spawn(#future);
+    }), method.span())?;
+    Ok(())
+}
+
+// `stopping` has to answer right away, so the actor keeps running while its future is waited on, and
+// terminates afterwards if it resolved to `Running::Stop`.
+// Still running when called means every `Addr` was dropped with nothing else left to run. Nothing would wake
+// the actor for the future then, so dropping an address of its own does, and it terminates once done either
+// way, as it would only be stopping again otherwise.
+fn stopping_hook(args: &HandlerArgs, method: &mut ImplItemFn) -> Result<()> {
+    let future = hook_future(args, &Translation::default(), method, "#[async_actor]")?;
+    let krate = &args.krate;
+    let (res, ctx, closed) = (internal_ident("__res"), internal_ident("__ctx"), internal_ident("__closed"));
+    method.block = translated_block(quote!({
+        use #krate::{ActorContext, ActorFutureExt, AsyncContext};
+        let #closed = #ctx.state() == #krate::ActorState::Running;
+        #ctx.wait(#future.map(move |#res, _, #ctx: &mut Self::Context| {
+            if #res == #krate::Running::Stop || #closed {
+                #ctx.terminate();
+            }
+        }));
+        if #closed {
+            let _ = #ctx.address();
+        }
+        #krate::Running::Continue
+    }), method.span())?;
+    Ok(())
+}
+
+// Translates the body of a hook taking the context last, as in `fn(&mut self, ctx)` or `fn(&mut self, item, ctx)`,
+// the ctx parameter then being the internal one
+fn hook_future(args: &HandlerArgs, translation: &Translation, method: &mut ImplItemFn, attr: &str) -> Result<TokenStream> {
+    let name = method.sig.ident.to_string();
+    let inputs_span = method.sig.inputs.span();
+    let mut inputs = method.sig.inputs.iter_mut().collect::<Vec<_>>();
+    let ctx_pat = match inputs.as_mut_slice() {
+        [FnArg::Receiver(_), .., FnArg::Typed(ctx)] => &mut *ctx.pat,
+        _ => return Err(Error::new(inputs_span, format!("{} `{}` takes `&mut self` and the context last", attr, name)))
+    };
+    // the hook itself needs the context to wait on the future
+    let future = ctx_future(args, translation, &method.block, ctx_pat, attr, true)?;
+    method.sig.asyncness = None;
+    Ok(future)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requires_actor_impl() {
+        assert!(async_actor_inner(&HandlerArgs::default(), quote! {
+            impl Handler<Ping> for AnActor {}
+        }).is_err());
+    }
+
+    #[test]
+    fn test_rejects_async_stopped() {
+        let error = async_actor_inner(&HandlerArgs::default(), quote! {
+            impl Actor for AnActor {
+                type Context = Context<Self>;
+                async fn stopped(&mut self, ctx: &mut Self::Context) {}
+            }
+        }).expect_err("").to_string();
+        assert_eq!(error, "#[async_actor] `stopped` can't be async, there's no actor left to run its future");
+    }
+
+    #[test]
+    fn test_keeps_sync_methods() {
+        let actor = quote! {
+            impl Actor for AnActor {
+                type Context = Context<Self>;
+                fn started(&mut self, ctx: &mut Self::Context) {
+                    ctx.set_mailbox_capacity(1);
+                }
+            }
+        };
+        let result = async_actor_inner(&HandlerArgs::default(), actor.clone()).expect("");
+        assert_eq!(result.to_string(), actor.to_string());
+    }
+}
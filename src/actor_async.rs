@@ -0,0 +1,136 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Block, Error, Expr, ExprClosure, Ident, Pat, Path, Result, Token};
+use syn::ext::IdentExt;
+use syn::parse::{Parse, ParseStream};
+use syn::spanned::Spanned;
+
+use crate::args::{path_value, HandlerArgs};
+use crate::r#impl::{actor_future_for, Translation};
+
+// `actor_async!(self, ctx, { .. })` takes the actor and ctx bindings around it, while the closure form
+// `actor_async!(|act, ctx| ..)` names them just for the body. Either may be preceded by `crate = path,`.
+pub struct ActorAsync {
+    krate: Option<Path>,
+    actor: Ident,
+    ctx: Option<Ident>,
+    body: Block,
+    is_closure: bool,
+}
+
+impl Parse for ActorAsync {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let krate = if input.peek(Token![crate]) && input.peek2(Token![=]) {
+            input.parse::<Token![crate]>()?;
+            let krate = path_value(input)?;
+            input.parse::<Token![,]>()?;
+            Some(krate)
+        } else {
+            None
+        };
+
+        if input.peek(Token![|]) || input.peek(Token![move]) {
+            let closure = input.parse::<ExprClosure>()?;
+            let (actor, ctx) = match closure.inputs.iter().collect::<Vec<_>>().as_slice() {
+                [actor, ctx] => (binding(actor)?, binding(ctx)?),
+                _ => return Err(Error::new(closure.inputs.span(), "actor_async! closures take the actor and the context, as in `|act, ctx|`"))
+            };
+            let actor = actor.ok_or_else(|| Error::new(closure.inputs.span(), "actor_async! needs a name for the actor"))?;
+            let body = match *closure.body {
+                Expr::Block(block) => block.block,
+                body => parse_quote!({ #body })
+            };
+            return Ok(ActorAsync { krate, actor, ctx, body, is_closure: true })
+        }
+
+        let actor = input.call(Ident::parse_any)?;
+        input.parse::<Token![,]>()?;
+        let ctx = if input.peek(Token![_]) {
+            input.parse::<Token![_]>()?;
+            None
+        } else {
+            Some(input.parse::<Ident>()?)
+        };
+        input.parse::<Token![,]>()?;
+        let body = input.parse::<Block>()?;
+        if !input.is_empty() {
+            input.parse::<Token![,]>()?;
+        }
+        Ok(ActorAsync { krate, actor, ctx, body, is_closure: false })
+    }
+}
+
+fn binding(pat: &Pat) -> Result<Option<Ident>> {
+    match pat {
+        Pat::Ident(ident) if ident.subpat.is_none() && ident.by_ref.is_none() => Ok(Some(ident.ident.clone())),
+        Pat::Wild(_) => Ok(None),
+        pat => Err(Error::new(pat.span(), "actor_async! closures take plain bindings or `_`"))
+    }
+}
+
+pub fn actor_async_impl(input: TokenStream) -> TokenStream {
+    actor_async_inner(&HandlerArgs::default(), input)
+        .unwrap_or_else(|e| e.to_compile_error())
+}
+
+// An `impl ActorFuture<Self>` running the body, used inside the actor impls, where `Self` is the actor
+pub fn actor_async_inner(args: &HandlerArgs, input: TokenStream) -> Result<TokenStream> {
+    let ActorAsync { krate, actor, ctx, body, is_closure } = syn::parse2::<ActorAsync>(input)?;
+    let args = HandlerArgs { krate: krate.unwrap_or_else(|| args.krate.clone()), ..args.clone() };
+
+    let (future, _) = actor_future_for(&args, &Translation::default(), &body, actor.to_string(), ctx.as_ref().map(|ctx| ctx.to_string()))?;
+
+    // The continuations get the context handed in again, so a ctx parameter only used by the block would be
+    // reported as unused. Only the context though, `self` isn't linted.
+    let used = ctx.filter(|_| !is_closure).map(|ctx| quote! { let _ = &#ctx; });
+
+    let krate = &args.krate;
+    Ok(quote! {{
+        #used
+        use #krate::ActorFutureExt;
+        #future
+    }})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_both_forms() {
+        let parsed = syn::parse2::<ActorAsync>(quote!(self, ctx, { ctx.stop() })).expect("");
+        assert_eq!(parsed.actor, "self");
+        assert!(!parsed.is_closure);
+
+        let parsed = syn::parse2::<ActorAsync>(quote!(|act, _| act.count += 1)).expect("");
+        assert_eq!(parsed.actor, "act");
+        assert!(parsed.ctx.is_none());
+        assert!(parsed.is_closure);
+    }
+
+    #[test]
+    fn test_parses_crate_path() {
+        let parsed = syn::parse2::<ActorAsync>(quote!(crate = facade::actix, |act, _| act.count += 1)).expect("");
+        assert_eq!(parsed.krate, Some(parse_quote!(facade::actix)));
+        assert_eq!(parsed.actor, "act");
+
+        let result = actor_async_inner(&HandlerArgs::default(), quote!(crate = "facade::actix", self, _, {})).expect("");
+        assert!(result.to_string().contains("use facade :: actix :: ActorFutureExt"), "{}", result);
+    }
+
+    #[test]
+    fn test_rejects_borrows_of_named_actor_across_awaits() {
+        let error = actor_async_inner(&HandlerArgs::default(), quote!(|act, _| {
+            let entry = &mut act.entry;
+            act.db.send(Ping).await;
+            entry.hits += 1;
+        })).expect_err("").to_string();
+        assert!(error.contains("`entry` borrows the actor state across an await. Every await resumes with a fresh borrow of `act`"), "{}", error);
+    }
+
+    #[test]
+    fn test_rejects_closures_without_ctx() {
+        let error = actor_async_inner(&HandlerArgs::default(), quote!(|act| act.count += 1)).expect_err("").to_string();
+        assert_eq!(error, "actor_async! closures take the actor and the context, as in `|act, ctx|`");
+    }
+}
@@ -0,0 +1,92 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Error, FnArg, ImplItemFn, Result, ReturnType};
+use syn::spanned::Spanned;
+
+use crate::args::{expand_with_args, HandlerArgs};
+use crate::r#impl::{actor_future, output_type, stub_fn, translated_block, Translation};
+
+pub fn actor_fn_impl(attribute: TokenStream, input: TokenStream) -> TokenStream {
+    expand_with_args(attribute, |args| expand_actor_fn(args, &Translation::default(), input))
+}
+
+// Expands a single #[actor_fn], keeping a stub method next to any error so callers don't error as well
+pub fn expand_actor_fn(args: &HandlerArgs, translation: &Translation, input: TokenStream) -> TokenStream {
+    actor_fn_inner(args, translation, input.clone())
+        .unwrap_or_else(|e| {
+            let error = e.to_compile_error();
+            let fallback = fallback_expansion(args, input);
+            quote! {
+                #error
+                #fallback
+            }
+        })
+}
+
+// The method returns an actor future running its body instead, so it can await while keeping access
+// to the actor state, and be awaited in turn from handlers and other #[actor_fn]s
+pub fn actor_fn_inner(args: &HandlerArgs, translation: &Translation, input: TokenStream) -> Result<TokenStream> {
+    let mut method = syn::parse2::<ImplItemFn>(input)?;
+
+    match method.sig.inputs.first() {
+        Some(FnArg::Receiver(receiver)) if receiver.reference.is_some() => {}
+        _ => return Err(Error::new(method.sig.span(), "#[actor_fn] methods take `&mut self` or `&self`, as they run on the actor"))
+    }
+
+    let (future, _) = actor_future(args, translation, &method.block, None)?;
+
+    let krate = &args.krate;
+    method.sig.asyncness = None;
+    method.sig.output = actor_future_output(args, &method.sig.output);
+    method.block = translated_block(quote!({
+        use #krate::ActorFutureExt;
+        #future
+    }), method.span())?;
+
+    Ok(quote! { #method })
+}
+
+fn actor_future_output(args: &HandlerArgs, output: &ReturnType) -> ReturnType {
+    let krate = &args.krate;
+    let output = output_type(output);
+    parse_quote!(-> impl #krate::ActorFuture<Self, Output = #output>)
+}
+
+// Keeps the method around with its translated signature, so callers don't error as well
+fn fallback_expansion(args: &HandlerArgs, input: TokenStream) -> TokenStream {
+    let mut method = match syn::parse2::<ImplItemFn>(input) {
+        Ok(method) => method,
+        Err(_) => return TokenStream::new()
+    };
+
+    // `!` would fall back to `()`, which isn't an actor future
+    let krate = &args.krate;
+    let output = output_type(&method.sig.output);
+    method.sig.output = actor_future_output(args, &method.sig.output);
+    stub_fn(&mut method, quote!(#krate::fut::ready::<#output>(loop {})));
+
+    quote! { #method }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requires_receiver() {
+        let error = actor_fn_inner(&HandlerArgs::default(), &Translation::default(), quote! {
+            fn refresh(key: u64) -> u64 { key }
+        }).expect_err("").to_string();
+        assert_eq!(error, "#[actor_fn] methods take `&mut self` or `&self`, as they run on the actor");
+    }
+
+    #[test]
+    fn test_stub_on_errors() {
+        let result = actor_fn_impl(quote!(), quote! {
+            fn refresh(self) -> u64 { 1 }
+        }).to_string();
+        assert!(result.contains("compile_error"));
+        assert!(result.contains("fn refresh (self) -> impl actix :: ActorFuture < Self , Output = u64 >"));
+        assert!(result.contains("actix :: fut :: ready :: < u64 > (loop { })"), "{}", result);
+    }
+}
@@ -0,0 +1,197 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Attribute, Error, Ident, LitStr, Path, Result, Token};
+use syn::ext::IdentExt;
+use syn::parse::{Parse, ParseStream};
+
+/// Options taken by the attribute, as in `#[async_handler(non_atomic, debug, crate = my::actix)]`
+#[derive(Clone)]
+pub struct HandlerArgs {
+    pub is_atomic: bool,
+    pub debug: bool,
+    /// also generates the inherent fn that `self.handle_inline(msg, ctx).await` calls from other handlers
+    pub inline: bool,
+    /// path used for every generated `actix` reference
+    pub krate: Path,
+    /// name the `Handler` trait is imported as
+    pub handler_trait: Ident,
+}
+
+impl Default for HandlerArgs {
+    fn default() -> Self {
+        HandlerArgs {
+            is_atomic: true,
+            debug: false,
+            inline: false,
+            krate: parse_quote!(actix),
+            handler_trait: parse_quote!(Handler),
+        }
+    }
+}
+
+impl HandlerArgs {
+    /// Options of the Handler impl generated for a single method, which may pick `#[atomic]` or `#[non_atomic]`
+    /// for itself. The method's other attributes are returned along, to be kept on the impl.
+    pub fn for_method<'a>(&self, attrs: &'a [Attribute]) -> Result<(HandlerArgs, Vec<&'a Attribute>)> {
+        // the generated impl names the trait itself
+        let mut args = HandlerArgs { handler_trait: parse_quote!(Handler), ..self.clone() };
+        let mut kept = vec![];
+        for attr in attrs {
+            if attr.path().is_ident("atomic") || attr.path().is_ident("non_atomic") {
+                attr.meta.require_path_only()?;
+                args.is_atomic = attr.path().is_ident("atomic");
+            } else {
+                kept.push(attr);
+            }
+        }
+        Ok((args, kept))
+    }
+}
+
+/// Expands an attribute with its options, or with the default ones next to the error if they don't parse,
+/// so the annotated item is still expanded
+pub fn expand_with_args(attribute: TokenStream, expand: impl FnOnce(&HandlerArgs) -> TokenStream) -> TokenStream {
+    let (args, args_error) = match syn::parse2::<HandlerArgs>(attribute) {
+        Ok(args) => (args, None),
+        Err(e) => (HandlerArgs::default(), Some(e.to_compile_error()))
+    };
+
+    let expansion = expand(&args);
+
+    quote! {
+        #args_error
+        #expansion
+    }
+}
+
+impl Parse for HandlerArgs {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let mut args = HandlerArgs::default();
+        let mut seen: Vec<Ident> = vec![];
+
+        while !input.is_empty() {
+            // Ident::parse_any, as options may be named after keywords
+            let key = input.call(Ident::parse_any)?;
+
+            if seen.contains(&key) {
+                return Err(Error::new(key.span(), format!("duplicate #[async_handler] option `{}`", key)))
+            }
+
+            match key.to_string().as_str() {
+                "atomic" | "non_atomic" => {
+                    if let Some(other) = seen.iter().find(|k| *k == "atomic" || *k == "non_atomic") {
+                        return Err(Error::new(key.span(), format!("`{}` conflicts with `{}`", key, other)))
+                    }
+                    flag(&key, input)?;
+                    args.is_atomic = key == "atomic";
+                }
+                "debug" => {
+                    flag(&key, input)?;
+                    args.debug = true;
+                }
+                "inline" => {
+                    flag(&key, input)?;
+                    args.inline = true;
+                }
+                "crate" => args.krate = path_value(input)?,
+                "trait" => {
+                    let path = path_value(input)?;
+                    args.handler_trait = path.segments.last().map(|s| s.ident.clone())
+                        .ok_or_else(|| Error::new(key.span(), "`trait` takes the path of the Handler trait"))?;
+                }
+                _ => return Err(Error::new(key.span(), format!(
+                    "unknown #[async_handler] option `{}`, expected one of `atomic`, `non_atomic`, `debug`, `inline`, `crate`, `trait`", key)))
+            }
+
+            seen.push(key);
+
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+
+        Ok(args)
+    }
+}
+
+fn flag(key: &Ident, input: ParseStream) -> Result<()> {
+    if input.peek(Token![=]) {
+        Err(Error::new(input.span(), format!("`{}` doesn't take a value", key)))
+    } else {
+        Ok(())
+    }
+}
+
+// Takes both `key = some::path` and `key = "some::path"`
+pub fn path_value(input: ParseStream) -> Result<Path> {
+    input.parse::<Token![=]>()?;
+    if input.peek(LitStr) {
+        input.parse::<LitStr>()?.parse()
+    } else {
+        input.call(Path::parse_mod_style)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::quote;
+
+    use super::*;
+
+    fn parse(tokens: proc_macro2::TokenStream) -> Result<HandlerArgs> {
+        syn::parse2::<HandlerArgs>(tokens)
+    }
+
+    #[test]
+    fn test_defaults_to_atomic() {
+        let args = parse(quote!()).expect("");
+        assert!(args.is_atomic);
+        assert!(!args.debug);
+    }
+
+    #[test]
+    fn test_parses_flags() {
+        let args = parse(quote!(non_atomic, debug, inline,)).expect("");
+        assert!(!args.is_atomic);
+        assert!(args.debug);
+        assert!(args.inline);
+    }
+
+    #[test]
+    fn test_parses_paths() {
+        let args = parse(quote!(crate = ::facade::actix)).expect("");
+        let krate = &args.krate;
+        assert_eq!(quote!(#krate).to_string(), ":: facade :: actix");
+
+        let args = parse(quote!(crate = "facade::actix")).expect("");
+        let krate = &args.krate;
+        assert_eq!(quote!(#krate).to_string(), "facade :: actix");
+
+        let args = parse(quote!(trait = facade::Handles)).expect("");
+        assert_eq!(args.handler_trait, "Handles");
+    }
+
+    #[test]
+    fn test_rejects_unknown_options() {
+        let error = parse(quote!(debug, atomik)).err().expect("").to_string();
+        assert_eq!(error, "unknown #[async_handler] option `atomik`, expected one of `atomic`, `non_atomic`, `debug`, `inline`, `crate`, `trait`");
+    }
+
+    #[test]
+    fn test_rejects_conflicting_options() {
+        let error = parse(quote!(non_atomic, atomic)).err().expect("").to_string();
+        assert_eq!(error, "`atomic` conflicts with `non_atomic`");
+    }
+
+    #[test]
+    fn test_rejects_duplicated_options() {
+        assert!(parse(quote!(debug, debug)).is_err());
+    }
+
+    #[test]
+    fn test_rejects_values_for_flags() {
+        assert!(parse(quote!(debug = true)).is_err());
+        let error = parse(quote!(non_atomic = true)).err().expect("").to_string();
+        assert_eq!(error, "`non_atomic` doesn't take a value");
+    }
+}
@@ -0,0 +1,284 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Attribute, Error, FnArg, Ident, ImplItem, ImplItemFn, Item, ItemImpl, ItemMod, Meta, Result};
+use syn::spanned::Spanned;
+
+use crate::actor_fn::expand_actor_fn;
+use crate::args::{expand_with_args, HandlerArgs};
+use crate::r#impl::{expand_handler, is_stream_handler, output_type, Translation};
+
+pub fn async_handlers_impl(attribute: TokenStream, input: TokenStream) -> TokenStream {
+    expand_with_args(attribute, |args| async_handlers_inner(args, input)
+        .unwrap_or_else(|e| e.to_compile_error()))
+}
+
+pub fn async_handlers_inner(args: &HandlerArgs, input: TokenStream) -> Result<TokenStream> {
+    match syn::parse2::<Item>(input)? {
+        Item::Impl(item_impl) => inherent_handlers(args, item_impl),
+        Item::Mod(item_mod) => module_handlers(args, &Translation::default(), item_mod),
+        item => Err(Error::new(item.span(), "#[async_handlers] can only be applied to an inherent impl or a module"))
+    }
+}
+
+// Every Handler or StreamHandler impl with an async method in the module gets translated with the module's options.
+// Impls with their own #[async_handler] are left for it to expand. The #[actor_fn]s of the inherent impls
+// are expanded along, so all of them can be awaited directly from the handlers.
+fn module_handlers(args: &HandlerArgs, translation: &Translation, mut item_mod: ItemMod) -> Result<TokenStream> {
+    let Some((_, items)) = &mut item_mod.content else {
+        return Err(Error::new(item_mod.span(), "#[async_handlers] can only be applied to inline modules"))
+    };
+
+    let mut translation = translation.clone();
+    for item in items.iter() {
+        match item {
+            Item::Impl(item_impl) if is_plain_inherent_impl(item_impl) => translation.actor_fns.extend(actor_fns(item_impl)),
+            _ => {}
+        }
+    }
+
+    for item in items.iter_mut() {
+        match item {
+            Item::Impl(item_impl) if is_async_handler(args, item_impl) => {
+                *item = Item::Verbatim(expand_handler(args, &translation, quote!(#item_impl)));
+            }
+            Item::Impl(item_impl) if is_plain_inherent_impl(item_impl) => {
+                for impl_item in item_impl.items.iter_mut() {
+                    match impl_item {
+                        ImplItem::Fn(method) if is_actor_fn(method) => *impl_item = expand_actor_fn_item(&translation, method.clone()),
+                        _ => {}
+                    }
+                }
+            }
+            Item::Mod(nested) if nested.content.is_some() => {
+                *item = Item::Verbatim(module_handlers(args, &translation, nested.clone())?);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(quote! { #item_mod })
+}
+
+// Stream handlers count as well, their `started` and `finished` hooks being async without `handle` being so
+fn is_async_handler(args: &HandlerArgs, item_impl: &ItemImpl) -> bool {
+    let is_handler = item_impl.trait_.as_ref()
+        .and_then(|(_, trait_, _)| trait_.segments.last())
+        .map(|last| last.ident == args.handler_trait)
+        .unwrap_or(false);
+    let has_async_fn = item_impl.items.iter()
+        .any(|item| matches!(item, ImplItem::Fn(method) if method.sig.asyncness.is_some()));
+    let annotated = item_impl.attrs.iter().any(|attr| is_attr(attr, "async_handler"));
+
+    (is_handler || is_stream_handler(item_impl)) && has_async_fn && !annotated
+}
+
+// Inherent impls with their own #[async_handlers] are left for it to expand
+fn is_plain_inherent_impl(item_impl: &ItemImpl) -> bool {
+    item_impl.trait_.is_none() && !item_impl.attrs.iter().any(|attr| is_attr(attr, "async_handlers"))
+}
+
+fn is_attr(attr: &Attribute, name: &str) -> bool {
+    attr.path().segments.last().map(|last| last.ident == name).unwrap_or(false)
+}
+
+fn is_actor_fn(method: &ImplItemFn) -> bool {
+    method.attrs.iter().any(|attr| is_attr(attr, "actor_fn"))
+}
+
+// #[actor_fn]s return actor futures already, so the handlers and other #[actor_fn]s expanded along with them
+// chain their futures as they are
+fn actor_fns(item_impl: &ItemImpl) -> Vec<Ident> {
+    item_impl.items.iter()
+        .filter_map(|item| match item {
+            ImplItem::Fn(method) if is_actor_fn(method) => Some(method.sig.ident.clone()),
+            _ => None
+        })
+        .collect()
+}
+
+// Expands the #[actor_fn] right away rather than leaving it to its attribute, taking the options given to it
+fn expand_actor_fn_item(translation: &Translation, mut method: ImplItemFn) -> ImplItem {
+    let mut attribute = TokenStream::new();
+    method.attrs.retain(|attr| {
+        if !is_attr(attr, "actor_fn") {
+            return true
+        }
+        if let Meta::List(list) = &attr.meta {
+            attribute = list.tokens.clone();
+        }
+        false
+    });
+    ImplItem::Verbatim(expand_with_args(attribute, |args| expand_actor_fn(args, translation, quote!(#method))))
+}
+
+// Every async method of the inherent impl becomes a Handler impl for its message type,
+// the other methods are left in the inherent impl
+fn inherent_handlers(args: &HandlerArgs, mut item_impl: ItemImpl) -> Result<TokenStream> {
+    if let Some((_, trait_, _)) = &item_impl.trait_ {
+        return Err(Error::new(trait_.span(),
+            "#[async_handlers] can only be applied to an inherent impl, use #[async_handler] for Handler impls"))
+    }
+
+    let translation = Translation { actor_fns: actor_fns(&item_impl), ..Default::default() };
+    let mut handlers = vec![];
+    let mut kept = vec![];
+    for item in std::mem::take(&mut item_impl.items) {
+        match item {
+            ImplItem::Fn(method) if is_actor_fn(&method) => kept.push(expand_actor_fn_item(&translation, method)),
+            ImplItem::Fn(method) if method.sig.asyncness.is_some() => {
+                handlers.push(handler_impl(args, &translation, &item_impl, method).unwrap_or_else(|e| e.to_compile_error()));
+            }
+            other => kept.push(other)
+        }
+    }
+
+    let inherent = if kept.is_empty() {
+        None
+    } else {
+        item_impl.items = kept;
+        Some(item_impl)
+    };
+
+    Ok(quote! {
+        #inherent
+        #(#handlers)*
+    })
+}
+
+fn handler_impl(args: &HandlerArgs, translation: &Translation, item_impl: &ItemImpl, method: ImplItemFn) -> Result<TokenStream> {
+    let (args, attrs) = args.for_method(&method.attrs)?;
+
+    if !method.sig.generics.params.is_empty() {
+        return Err(Error::new(method.sig.generics.span(),
+            "#[async_handlers] methods can't be generic, each one handles a single message type"))
+    }
+
+    let inputs = method.sig.inputs.iter().collect::<Vec<_>>();
+    let (msg, ctx) = match inputs.as_slice() {
+        [FnArg::Receiver(receiver), FnArg::Typed(msg), FnArg::Typed(ctx)]
+            if receiver.reference.is_some() && receiver.mutability.is_some() => (msg, ctx),
+        _ => return Err(Error::new(method.sig.inputs.span(),
+            "#[async_handlers] methods take `&mut self`, the message and the context"))
+    };
+
+    let msg_ty = &msg.ty;
+    let result_ty = output_type(&method.sig.output);
+    let (impl_generics, _, where_clause) = item_impl.generics.split_for_impl();
+    let self_ty = &item_impl.self_ty;
+    let krate = &args.krate;
+    let block = &method.block;
+
+    let handler = quote! {
+        #(#attrs)*
+        impl #impl_generics #krate::Handler<#msg_ty> for #self_ty #where_clause {
+            type Result = #result_ty;
+
+            async fn handle(&mut self, #msg, #ctx) -> Self::Result #block
+        }
+    };
+
+    Ok(expand_handler(&args, translation, handler))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requires_inherent_impl() {
+        assert!(async_handlers_inner(&HandlerArgs::default(), quote! {
+            impl Handler<Ping> for AnActor {}
+        }).is_err());
+    }
+
+    #[test]
+    fn test_requires_inline_module() {
+        assert!(async_handlers_inner(&HandlerArgs::default(), quote! { mod handlers; }).is_err());
+        assert!(async_handlers_inner(&HandlerArgs::default(), quote! { struct AnActor; }).is_err());
+    }
+
+    #[test]
+    fn test_keeps_other_items_in_modules() {
+        let module = quote! {
+            mod handlers {
+                use super::*;
+
+                impl Handler<Ping> for AnActor {
+                    type Result = u64;
+                    fn handle(&mut self, msg: Ping, ctx: &mut Context<Self>) -> u64 { 1 }
+                }
+
+                #[async_handler(non_atomic)]
+                impl Handler<Pong> for AnActor {
+                    type Result = ();
+                    async fn handle(&mut self, msg: Pong, ctx: &mut Context<Self>) {}
+                }
+            }
+        };
+        let result = async_handlers_inner(&HandlerArgs::default(), module.clone()).expect("");
+        assert_eq!(result.to_string(), module.to_string());
+    }
+
+    #[test]
+    fn test_translates_stream_handler_hooks_in_modules() {
+        let result = async_handlers_inner(&HandlerArgs::default(), quote! {
+            mod handlers {
+                impl StreamHandler<Tick> for AnActor {
+                    fn handle(&mut self, item: Tick, ctx: &mut Context<Self>) {}
+
+                    async fn started(&mut self, ctx: &mut Context<Self>) {
+                        self.delegate.send(Ping).await;
+                    }
+                }
+            }
+        }).expect("").to_string();
+        assert!(result.contains("fn started (& mut self , __ctx : & mut Context < Self >)"), "{}", result);
+    }
+
+    #[test]
+    fn test_chains_actor_fns_only() {
+        let result = async_handlers_inner(&HandlerArgs::default(), quote! {
+            impl AnActor {
+                #[actor_fn]
+                fn refresh(&mut self) -> u64 { 1 }
+
+                fn fetch(&self) -> Ready<u64> { ready(1) }
+
+                async fn ping(&mut self, msg: Ping, ctx: &mut Context<Self>) -> u64 {
+                    let refreshed = self.refresh().await;
+                    let fetched = self.fetch().await;
+                    refreshed + fetched
+                }
+            }
+        }).expect("").to_string();
+        assert!(result.contains("fn refresh (& mut self) -> impl actix :: ActorFuture < Self , Output = u64 >"), "{}", result);
+        assert!(result.contains("{ __self . refresh () . then"), "{}", result);
+        assert!(result.contains("__SelfCall (std :: cell :: Cell :: new (Some (__self . fetch ()))"), "{}", result);
+    }
+
+    #[test]
+    fn test_keeps_other_methods() {
+        let result = async_handlers_inner(&HandlerArgs::default(), quote! {
+            impl AnActor {
+                fn helper(&self) -> u64 { 1 }
+            }
+        }).expect("");
+        assert_eq!(result.to_string(), quote! {
+            impl AnActor {
+                fn helper(&self) -> u64 { 1 }
+            }
+        }.to_string());
+    }
+
+    #[test]
+    fn test_rejects_invalid_methods() {
+        let result = async_handlers_inner(&HandlerArgs::default(), quote! {
+            impl AnActor {
+                async fn ping(&self, msg: Ping, ctx: &mut Context<Self>) {}
+                async fn pong<M>(&mut self, msg: M, ctx: &mut Context<Self>) {}
+            }
+        }).expect("").to_string();
+        assert!(result.contains("#[async_handlers] methods take `&mut self`, the message and the context"));
+        assert!(result.contains("#[async_handlers] methods can't be generic, each one handles a single message type"));
+    }
+}
@@ -1,56 +1,407 @@
-use proc_macro2::{Span, TokenStream, TokenTree};
+use proc_macro2::{Group, Literal, Spacing, Span, TokenStream, TokenTree};
 use quote::{quote, ToTokens};
 use rust_format::Error::BadSourceCode;
 use rust_format::Formatter;
-use syn::{Block, Error, Expr, ExprAssign, ExprAwait, ExprBlock, ExprForLoop, ExprIf, Ident, ImplItem, ImplItemFn, ImplItemType, ItemImpl, Local, LocalInit, Macro, Pat, Result, Stmt};
+use syn::{Arm, Attribute, Block, Error, Expr, ExprAssign, ExprAwait, ExprBlock, ExprCall, ExprClosure, ExprField, ExprForLoop, ExprIf, ExprIndex, ExprLet, ExprMacro, ExprMethodCall, ExprParen, ExprPath, ExprReference, ExprStruct, ExprTuple, ExprWhile, FieldValue, GenericArgument, Ident, ImplItem, ImplItemFn, ImplItemType, Item, ItemImpl, LitStr, Local, LocalInit, Macro, Member, Pat, Path, PathArguments, PatIdent, PatOr, PatParen, PatReference, PatSlice, PatStruct, PatTuple, PatTupleStruct, PatType, Result, ReturnType, Stmt, StmtMacro, Type};
 use syn::FnArg::Typed;
+
+use crate::actor::stream_handler_inner;
+use crate::args::HandlerArgs;
 use syn::fold::Fold;
+use syn::parse::Parser;
 use syn::spanned::Spanned;
 
 // inspiration https://github.com/alexcrichton/futures-await/blob/0cd2c3f10d5b0b978836e843a272a590ba574434/futures-await-async-macro/src/lib.rs#L401
 
 pub fn async_handler_impl(attribute: TokenStream, input: TokenStream) -> TokenStream {
 
-    let is_atomic = match &attribute.to_string() as &str {
-        "non_atomic" => false,
-        "" => true,
-        _ => panic!("the #[async_handler] attribute currently only takes `non_atomic` as an arg"),
+    let args = match syn::parse2::<HandlerArgs>(attribute) {
+        Ok(args) => args,
+        Err(e) => {
+            let error = e.to_compile_error();
+            let fallback = fallback_expansion(&HandlerArgs::default(), input);
+            return quote! {
+                #error
+                #fallback
+            }
+        }
+    };
+
+    expand_handler(&args, &Translation::default(), input)
+}
+
+// Expands a single Handler impl, keeping a stub impl next to any error so they don't cascade
+pub fn expand_handler(args: &HandlerArgs, translation: &Translation, input: TokenStream) -> TokenStream {
+    let dump_error = if args.debug {
+        write_debug_dump(args, translation, input.clone()).err().map(|e| e.to_compile_error())
+    } else {
+        None
+    };
+
+    let expansion = async_handler_inner(args, translation, input.clone())
+        .unwrap_or_else(|e| {
+            let error = e.to_compile_error();
+            let fallback = fallback_expansion(args, input);
+            quote! {
+                #error
+                #fallback
+            }
+        });
+
+    quote! {
+        #dump_error
+        #expansion
+    }
+}
+
+const SEGMENT_MARKER: &str = "__async_handler_segment!";
+
+// Writes the pretty-printed translation to `target/async_handler`, with a comment at the start of each
+// continuation naming the user statement that split it off.
+fn write_debug_dump(args: &HandlerArgs, translation: &Translation, input: TokenStream) -> Result<()> {
+    let item_impl = syn::parse2::<ItemImpl>(input.clone())?;
+    let dump = debug_dump(args, translation, input)?;
+
+    let dir = std::env::var_os("CARGO_TARGET_DIR")
+        .map(std::path::PathBuf::from)
+        .or_else(|| std::env::var_os("CARGO_MANIFEST_DIR").map(|dir| std::path::Path::new(&dir).join("target")))
+        .unwrap_or_else(|| "target".into())
+        .join("async_handler");
+
+    let trait_ = item_impl.trait_.as_ref().map(|t| t.1.to_token_stream()).unwrap_or_default();
+    let self_ty = &item_impl.self_ty;
+    let name = quote!(#self_ty #trait_).to_string()
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("_");
+
+    std::fs::create_dir_all(&dir)
+        .and_then(|_| std::fs::write(dir.join(format!("{}.rs", name)), dump))
+        .map_err(|e| Error::new(Span::call_site(), format!("#[async_handler(debug)] couldn't write the expansion: {}", e)))
+}
+
+fn debug_dump(args: &HandlerArgs, translation: &Translation, input: TokenStream) -> Result<String> {
+    let translated = async_handler_inner(args, &Translation { markers: true, ..translation.clone() }, input)?;
+    let formatted = rust_format::RustFmt::default().format_tokens(translated)
+        .map_err(|e| Error::new(Span::call_site(), e))?;
+
+    let mut dump = String::new();
+    let mut rest = formatted.as_str();
+    while let Some(start) = rest.find(SEGMENT_MARKER) {
+        dump.push_str(&rest[..start]);
+        dump.push_str("// after: ");
+        let mut chars = rest[start..].char_indices().skip_while(|(_, c)| *c != '"').skip(1);
+        let mut end = rest.len() - start;
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => if let Some((_, escaped)) = chars.next() { dump.push(escaped) },
+                '"' => {
+                    end = rest[start + i..].find(';').map(|semi| i + semi + 1).unwrap_or(end);
+                    break
+                }
+                c => dump.push(c)
+            }
+        }
+        rest = &rest[start + end..];
+    }
+    dump.push_str(rest);
+    Ok(dump)
+}
+
+fn segment_marker(stmt: &Stmt) -> TokenStream {
+    let mut source = stmt.to_token_stream().to_string().replace("__self", "self");
+    for (spaced, compact) in [(" . ", "."), (" (", "("), ("( ", "("), (" )", ")"), (" [", "["), ("[ ", "["),
+        (" ]", "]"), (" ;", ";"), (" ,", ","), (" !", "!"), ("& ", "&"), (" ::", "::"), (":: ", "::")] {
+        source = source.replace(spaced, compact);
+    }
+    if source.chars().count() > 80 {
+        source = source.chars().take(79).chain(Some('…')).collect();
+    }
+    let marker = Ident::new(SEGMENT_MARKER.trim_end_matches('!'), Span::call_site());
+    quote!(#marker!(#source);)
+}
+
+// Keeps the impl around when the translation fails, with the original body inside a stub `handle`.
+// Otherwise the IDE loses the Handler impl, and completions within the body, while the user is typing.
+fn fallback_expansion(args: &HandlerArgs, input: TokenStream) -> TokenStream {
+    let mut item_impl = match syn::parse2::<ItemImpl>(input) {
+        Ok(item_impl) => item_impl,
+        Err(_) => return TokenStream::new()
     };
 
-    async_handler_inner(is_atomic, input)
-        .unwrap_or_else(|e| e.to_compile_error())
+    // stream handlers return nothing, but may have async `started` and `finished` as well
+    let is_stream = is_stream_handler(&item_impl);
+    if !is_stream {
+        let _ = infer_result_type(&mut item_impl);
+    }
+
+    for item in &mut item_impl.items {
+        match item {
+            ImplItem::Type(ref mut body) if body.ident == "Result" => {
+                let _ = process_result_type(args, body);
+            }
+            ImplItem::Fn(ref mut body) if body.sig.ident == "handle" || (is_stream && body.sig.asyncness.is_some()) => {
+                if !is_stream {
+                    body.sig.output = parse_quote! { -> Self::Result };
+                }
+                stub_fn(body, quote!(loop {}));
+            }
+            _ => {}
+        }
+    }
+
+    quote! { #item_impl }
+}
+
+// Keeps the original block inside a method that type-checks without running it, returning `value`, which
+// diverges. Stubs are only emitted next to a compile error, so they never run.
+pub fn stub_fn(method: &mut ImplItemFn, value: TokenStream) {
+    method.sig.asyncness = None;
+    let block = &method.block;
+    method.block = parse_quote!({
+        #[allow(unreachable_code, unused_variables)]
+        {
+            let _ = async move #block;
+            #value
+        }
+    });
+}
+
+// What a translation is told besides the options of the attribute
+#[derive(Clone, Default)]
+pub struct Translation {
+    /// marks where each continuation comes from, for the debug dump
+    pub markers: bool,
+    /// #[actor_fn] methods expanded along, whose futures are chained as they are when awaited
+    pub actor_fns: Vec<Ident>,
 }
 
-pub fn async_handler_inner(is_atomic: bool, input: TokenStream) -> Result<TokenStream> {
+pub fn async_handler_inner(args: &HandlerArgs, translation: &Translation, input: TokenStream) -> Result<TokenStream> {
     let mut item_fn = syn::parse2::<ItemImpl>(input.clone())?;
 
+    if is_stream_handler(&item_fn) {
+        return stream_handler_inner(args, translation, item_fn)
+    }
+
+    // matching the last segment, so `actix::Handler<M>` and `actix::prelude::Handler<M>` are taken as well
     let is_handler = item_fn.trait_.as_ref()
-        .and_then(|trait_| trait_.1.segments.first().map(|i| "Handler" == i.ident.to_string()))
+        .and_then(|trait_| trait_.1.segments.last())
+        .map(|last| last.ident == args.handler_trait && matches!(last.arguments, PathArguments::AngleBracketed(_)))
         .unwrap_or(false);
 
     if !is_handler {
         return Err(Error::new(input.span(), "#[async_handler] can only be applied to an actor Handler impl"))
     }
 
+    let inferred = infer_result_type(&mut item_fn)?;
+
+    if is_sync_handler(&item_fn) {
+        if let Some(ty) = inferred {
+            sync_message_result(args, &mut item_fn, ty);
+        }
+        return Ok(quote! { #item_fn })
+    }
+
+    // `type Result` may already name the actor future, which then decides atomicity
+    let declared = item_fn.items.iter().find_map(|item| match item {
+        ImplItem::Type(body) if body.ident == "Result" => declared_response(&body.ty),
+        _ => None
+    });
+    let args = &match declared {
+        Some(true) if !args.is_atomic => return Err(Error::new(input.span(),
+            "#[async_handler] `type Result` is an AtomicResponse, which conflicts with `non_atomic`")),
+        Some(is_atomic) => HandlerArgs { is_atomic, ..args.clone() },
+        None => args.clone()
+    };
+
+    let inline = if args.inline {
+        // the inherent fn can't take the generics of the impl
+        if !item_fn.generics.params.is_empty() {
+            return Err(Error::new(item_fn.generics.span(), "#[async_handler] `inline` handlers can't be generic impls"))
+        }
+        Some(inline_handler(&item_fn).ok_or_else(|| Error::new(input.span(),
+            "#[async_handler] `inline` needs the message type and `type Result` to be named"))?)
+    } else {
+        None
+    };
+
+    let mut inline_fn = None;
     for item in &mut item_fn.items {
         match item {
-            ImplItem::Type(ref mut body) if "Result" == body.ident.to_string() => {
-                process_result_type(is_atomic, body)?;
+            ImplItem::Type(ref mut body) if body.ident == "Result" => {
+                process_result_type(args, body)?;
+            }
+            ImplItem::Fn(ref mut body) if body.sig.ident == "handle" => {
+                inline_fn = process_handler_fn(args, translation, body, inline.clone())?;
+            }
+            _ => {}
+        }
+    }
+
+    let self_ty = &item_fn.self_ty;
+    let inherent = inline_fn.map(|inline_fn| quote! {
+        impl #self_ty {
+            #inline_fn
+        }
+    });
+
+    Ok(quote! {
+        #item_fn
+        #inherent
+    })
+}
+
+// The name of the inherent fn that `self.handle_inline(msg, ctx).await` calls in other handlers of the actor,
+// and the result type of the actor future it returns
+fn inline_handler(item_impl: &ItemImpl) -> Option<(Ident, Type)> {
+    let message = item_impl.trait_.as_ref()
+        .and_then(|trait_| trait_.1.segments.last())
+        .and_then(|last| match &last.arguments {
+            PathArguments::AngleBracketed(generics) => generics.args.first(),
+            _ => None
+        })
+        .and_then(|message| match message {
+            GenericArgument::Type(Type::Path(path)) => path.path.segments.last(),
+            _ => None
+        })?;
+
+    let result_ty = item_impl.items.iter().find_map(|item| match item {
+        ImplItem::Type(body) if body.ident == "Result" => Some(&body.ty),
+        _ => None
+    })?;
+    let result_ty = match declared_response(result_ty) {
+        // `AtomicResponse<Self, T>` and `ResponseActFuture<Self, T>`
+        Some(_) => match &result_ty {
+            Type::Path(path) => match &path.path.segments.last()?.arguments {
+                PathArguments::AngleBracketed(generics) => match generics.args.iter().nth(1)? {
+                    GenericArgument::Type(ty) => ty.clone(),
+                    _ => return None
+                },
+                _ => return None
+            },
+            _ => return None
+        },
+        None => result_ty.clone()
+    };
+
+    Some((inline_handler_ident(&message.ident), result_ty))
+}
+
+fn inline_handler_ident(message: &Ident) -> Ident {
+    let mut name = String::from("__handle");
+    for c in message.to_string().chars() {
+        if c.is_uppercase() || name.ends_with("handle") {
+            name.push('_');
+        }
+        name.extend(c.to_lowercase());
+    }
+    Ident::new(&name, Span::call_site())
+}
+
+pub fn is_stream_handler(item_impl: &ItemImpl) -> bool {
+    item_impl.trait_.as_ref()
+        .and_then(|trait_| trait_.1.segments.last())
+        .map(|last| last.ident == "StreamHandler")
+        .unwrap_or(false)
+}
+
+fn handle_fn(item_impl: &ItemImpl) -> Option<&ImplItemFn> {
+    item_impl.items.iter().find_map(|item| match item {
+        ImplItem::Fn(body) if body.sig.ident == "handle" => Some(body),
+        _ => None
+    })
+}
+
+// Without `type Result`, takes it from the return type declared by `handle`, as in `async fn handle(..) -> u64`
+fn infer_result_type(item_impl: &mut ItemImpl) -> Result<Option<Type>> {
+    let has_result_type = item_impl.items.iter()
+        .any(|item| matches!(item, ImplItem::Type(body) if body.ident == "Result"));
+    if has_result_type {
+        return Ok(None)
+    }
+
+    let Some(handle) = handle_fn(item_impl) else {
+        return Ok(None)
+    };
+
+    let ty = match &handle.sig.output {
+        ReturnType::Type(_, ty) if !is_self_result(ty) => ty.clone(),
+        output => return Err(Error::new(
+            if let ReturnType::Type(_, ty) = output { ty.span() } else { handle.sig.ident.span() },
+            "#[async_handler] needs either `type Result` in the impl or the result type as the return type of `handle`"))
+    };
+
+    item_impl.items.insert(0, parse_quote! { type Result = #ty; });
+    Ok(Some(*ty))
+}
+
+// A `handle` that isn't async and doesn't await has nothing to translate
+fn is_sync_handler(item_impl: &ItemImpl) -> bool {
+    handle_fn(item_impl)
+        .map(|handle| handle.sig.asyncness.is_none() && split_awaits(&handle.block, &HandlerArgs::default(), &Translation::default()).len() == 1)
+        .unwrap_or(false)
+}
+
+// An inferred result type of a sync handler may not be a `MessageResponse`, so it's returned through
+// `MessageResult`, which takes any type.
+fn sync_message_result(args: &HandlerArgs, item_impl: &mut ItemImpl, ty: Type) {
+    let krate = &args.krate;
+    let message = item_impl.trait_.as_ref()
+        .and_then(|trait_| trait_.1.segments.last())
+        .and_then(|last| match &last.arguments {
+            PathArguments::AngleBracketed(generics) => generics.args.first(),
+            _ => None
+        });
+
+    for item in &mut item_impl.items {
+        match item {
+            ImplItem::Type(body) if body.ident == "Result" => {
+                body.ty = parse_quote! { #krate::MessageResult<#message> };
             }
-            ImplItem::Fn(ref mut body) if "handle" == body.sig.ident.to_string() => {
-                process_handler_fn(is_atomic, body)?;
+            ImplItem::Fn(body) if body.sig.ident == "handle" => {
+                let block = &body.block;
+                body.sig.output = parse_quote! { -> Self::Result };
+                // the closure keeps any `return` in the block returning the bare value
+                body.block = parse_quote!({
+                    #[allow(clippy::redundant_closure_call)]
+                    let result = (|| -> #ty #block)();
+                    #krate::MessageResult(result)
+                });
             }
             _ => {}
         }
     }
+}
+
+// `Some(is_atomic)` when the type already is an actor future response
+fn declared_response(ty: &Type) -> Option<bool> {
+    match ty {
+        Type::Path(path) => match path.path.segments.last() {
+            Some(last) if last.ident == "AtomicResponse" => Some(true),
+            Some(last) if last.ident == "ResponseActFuture" => Some(false),
+            _ => None
+        },
+        _ => None
+    }
+}
 
-    return Ok(quote! { #item_fn })
+fn is_self_result(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path.qself.is_none() && path.path.segments.len() == 2
+            && path.path.segments[0].ident == "Self" && path.path.segments[1].ident == "Result",
+        _ => false
+    }
 }
 
-fn process_result_type(is_atomic: bool, body: &mut ImplItemType) -> Result<()> {
-    let result_type = result_type_ident(is_atomic, body.span());
+fn process_result_type(args: &HandlerArgs, body: &mut ImplItemType) -> Result<()> {
+    if declared_response(&body.ty).is_some() {
+        return Ok(())
+    }
+    let result_type = result_type_ident(args.is_atomic, body.span());
     let item_ty = &body.ty;
-    body.ty = parse_quote! { actix::#result_type <Self, #item_ty > };
+    let krate = &args.krate;
+    body.ty = parse_quote! { #krate::#result_type <Self, #item_ty > };
     Ok(())
 }
 
@@ -62,52 +413,151 @@ fn result_type_ident(is_atomic: bool, span: Span) -> Ident {
     }
 }
 
-fn process_handler_fn(is_atomic: bool, body: &mut ImplItemFn) -> Result<()> {
+// Returns the inherent fn building the actor future when asked for one, `handle` then just calls it
+fn process_handler_fn(args: &HandlerArgs, translation: &Translation, body: &mut ImplItemFn, inline: Option<(Ident, Type)>) -> Result<Option<ImplItemFn>> {
     body.sig.asyncness = None;
 
     body.sig.output = parse_quote! { -> Self::Result };
 
-    let ctx_ident = if let Some(Typed(t)) = body.sig.inputs.last() {
-        if let Pat::Ident(ident) = &*t.pat {
-            Some(ident.ident.to_string())
-        } else {
-            None
+    if body.sig.inputs.len() != 3 {
+        return Err(Error::new(body.sig.inputs.span(), "#[async_handler] invalid argument types for Handler impl"))
+    }
+
+    // the message may be destructured at will, its bindings are just moved along the continuations
+    let ctx_pat = match body.sig.inputs.last_mut() {
+        Some(Typed(t)) => &mut *t.pat,
+        _ => return Err(Error::new(body.sig.inputs.span(), "#[async_handler] invalid argument types for Handler impl"))
+    };
+    let future = ctx_future(args, translation, &body.block, ctx_pat, "#[async_handler]", false)?;
+
+    let krate = &args.krate;
+    let (future, ext, inline_fn) = match inline {
+        Some((ident, result_ty)) => {
+            let mut inline_fn = body.clone();
+            inline_fn.sig.ident = ident.clone();
+            inline_fn.sig.output = parse_quote! { -> impl #krate::ActorFuture<Self, Output = #result_ty> };
+            // `Self::Context` is ambiguous outside the Handler impl
+            if let Some(Typed(ctx)) = inline_fn.sig.inputs.last_mut() {
+                ctx.ty = parse_quote! { &mut <Self as #krate::Actor>::Context };
+            }
+            inline_fn.block = translated_block(quote!({
+                use #krate::ActorFutureExt;
+                #future
+            }), body.span())?;
+
+            let (msg, ctx) = (internal_ident("__msg"), internal_ident("__ctx"));
+            for (input, ident) in body.sig.inputs.iter_mut().skip(1).zip([&msg, &ctx]) {
+                if let Typed(input) = input {
+                    *input.pat = parse_quote!(#ident);
+                }
+            }
+            (quote! { Box::pin(self.#ident(#msg, #ctx)) }, None, Some(inline_fn))
         }
+        None => (quote! { Box::pin(#future) }, Some(quote! { use #krate::ActorFutureExt; }), None)
+    };
+
+    // ResponseActFuture is just an alias for the pinned future
+    let response = if args.is_atomic {
+        quote! { #krate::AtomicResponse::new(#future) }
     } else {
-        None
-    }.ok_or(Error::new(body.span(), "#[async_handler] invalid argument types for Handler impl"))?;
+        future
+    };
+
+    body.block = translated_block(quote!({
+        #ext
+        #response
+    }), body.span())?;
+    Ok(inline_fn)
+}
 
-    let self_renamed = RenameParams(ctx_ident).fold_block(body.clone().block);
+// Translates the body of a fn taking the context as `ctx_pat`, which has to be a plain binding or `_`.
+// Every use of ctx moves into the continuations, so the parameter itself would be reported as unused:
+// it's bound to the internal `__ctx` instead. Only left as written when never used, so that warning
+// still reaches the user, unless the caller `needs_ctx` itself.
+pub fn ctx_future(args: &HandlerArgs, translation: &Translation, block: &Block, ctx_pat: &mut Pat, attr: &str, needs_ctx: bool) -> Result<TokenStream> {
+    let ctx_ident = match ctx_pat {
+        Pat::Ident(ident) if ident.subpat.is_none() => Some(ident.ident.to_string()),
+        Pat::Wild(_) => None,
+        pat => return Err(Error::new(pat.span(), format!("{} the context parameter must be a plain binding or `_`", attr)))
+    };
 
-    let awaits = split_awaits(&self_renamed);
+    let (future, ctx_renamed) = actor_future(args, translation, block, ctx_ident)?;
 
-    let future_chain = build_future_chain(awaits, true, None);
+    if needs_ctx || ctx_renamed || matches!(ctx_pat, Pat::Wild(_)) {
+        *ctx_pat = Pat::Ident(PatIdent {
+            attrs: vec![],
+            by_ref: None,
+            mutability: None,
+            ident: internal_ident("__ctx"),
+            subpat: None,
+        });
+    }
+    Ok(future)
+}
+
+// The type a fn returns, `()` when left out
+pub fn output_type(output: &ReturnType) -> TokenStream {
+    match output {
+        ReturnType::Default => quote!(()),
+        ReturnType::Type(_, ty) => quote!(#ty)
+    }
+}
+
+// Translates the block into an actor future running it, a chain of continuations starting from a ready one.
+// Also tells whether ctx was used, as the continuations get it instead.
+pub fn actor_future(args: &HandlerArgs, translation: &Translation, block: &Block, ctx_ident: Option<String>) -> Result<(TokenStream, bool)> {
+    actor_future_for(args, translation, block, "self".to_string(), ctx_ident)
+}
+
+// Same as `actor_future`, with the actor named by another binding than `self`
+pub fn actor_future_for(args: &HandlerArgs, translation: &Translation, block: &Block, actor: String, ctx_ident: Option<String>) -> Result<(TokenStream, bool)> {
+    check_state_borrows(block, &actor, ctx_ident.as_deref(), vec![])?;
 
-    let result_type = result_type_ident(is_atomic, body.span());
+    let mut rename = RenameParams::new(ctx_ident);
+    rename.actor = actor;
+    let self_renamed = rename.fold_block(block.clone());
+
+    let awaits = split_awaits(&self_renamed, args, translation);
+
+    let future_chain = build_future_chain(awaits, true, None);
+    // a trailing `fut.await;` leaves the block with a unit value, while the chain would resolve to the output of `fut`
+    let unit = awaits_last(block).then(|| quote!(.map(|_, _, _| {})));
 
-    let block = quote!({
-        use actix::ActorFutureExt;
-        actix::#result_type::new(Box::pin(actix::fut::wrap_future::<_, Self>(actix::fut::ready(()))
+    let krate = &args.krate;
+    let future = quote! {
+        #krate::fut::wrap_future::<_, Self>(#krate::fut::ready(()))
             #future_chain
-       ))
-    });
+            #unit
+    };
+    Ok((future, rename.ctx_renamed))
+}
 
-    match syn::parse2::<Block>(block.clone()) {
-        Ok(block) => {
-            body.block = block;
-            Ok(())
-        }
-        Err(e) => {
-            // Errors from parse2 are very short, let's try to use the compiler via RustFmt instead
-            match rust_format::RustFmt::default().format_tokens(quote!( fn handler() { #block })) {
-                Err(BadSourceCode(e)) => Err(Error::new(body.span(), e)),
-                _ => Err(e) // we couldn't get more detail from the formatter, just throw whatever we had,
-            }
-        }
+fn awaits_last(block: &Block) -> bool {
+    match block.stmts.last() {
+        Some(Stmt::Expr(Expr::Await(_), Some(_))) => true,
+        Some(Stmt::Expr(Expr::Macro(ExprMacro { mac, .. }), Some(_)) | Stmt::Macro(StmtMacro { mac, .. })) => is_actor_await(mac),
+        _ => false
     }
 }
 
+pub fn translated_block(block: TokenStream, span: Span) -> Result<Block> {
+    syn::parse2::<Block>(block.clone()).map_err(|e| {
+        // Errors from parse2 are very short, let's try to use the compiler via RustFmt instead
+        match rust_format::RustFmt::default().format_tokens(quote!( fn handler() { #block })) {
+            Err(BadSourceCode(e)) => Error::new(span, e),
+            _ => e // we couldn't get more detail from the formatter, just throw whatever we had,
+        }
+    })
+}
+
+// The parameters every continuation gets. Mixed site hygiene keeps user variables of the same name
+// apart, user code only reaches them through the renamed `self` and ctx, see `RenameParams`.
+pub fn internal_ident(name: &str) -> Ident {
+    Ident::new(name, Span::mixed_site())
+}
+
 fn build_future_chain(awaits: Vec<TokenStream>, enclose_first: bool, mut return_unit: Option<TokenStream>) -> TokenStream {
+    let (res, this, ctx) = (internal_ident("__res"), internal_ident("__self"), internal_ident("__ctx"));
     awaits.iter().rfold(None, |acc, await_block|
         match acc {
             Some((count, inner))
@@ -115,21 +565,21 @@ fn build_future_chain(awaits: Vec<TokenStream>, enclose_first: bool, mut return_
                     { #await_block #inner }
             })),
             Some((count, inner)) => Some((count + 1, quote! {
-                .then(move |__res, __self, __ctx| {
+                .then(move |#res, #this, #ctx| {
                     #await_block #inner
                 })
             })),
             None if return_unit.is_some() => {
                 let return_value = return_unit.take().unwrap();
                 Some((1, quote! {
-                    .map(move |__res, __self, __ctx| {
+                    .map(move |#res, #this, #ctx| {
                         #await_block;
                         #return_value
                     })
                 }))
             },
             None if !await_block.is_empty() => Some((1, quote! {
-                .map(move |__res, __self, __ctx| {
+                .map(move |#res, #this, #ctx| {
                     #await_block
                 })
             })),
@@ -138,92 +588,157 @@ fn build_future_chain(awaits: Vec<TokenStream>, enclose_first: bool, mut return_
     ).unwrap_or((0, quote!())).1
 }
 
-fn split_awaits(block: &Block) -> Vec<TokenStream> {
+fn split_awaits(block: &Block, args: &HandlerArgs, translation: &Translation) -> Vec<TokenStream> {
+    let res = internal_ident("__res");
     let mut parts = vec!(TokenStream::new());
     for stmt in &block.stmts {
+        let split_at = parts.len();
+        let attrs = stmt_attrs(stmt);
+        // kept apart while splitting, so only the awaited future ends up in the last part
+        let before = std::mem::take(parts.last_mut().unwrap());
         if !match stmt {
-            Stmt::Expr(Expr::Await(expr), _) => {
-                expr_await(&mut parts, expr);
-                true
+            Stmt::Expr(expr @ (Expr::Await(_) | Expr::Macro(_)), _) => {
+                expr_await(&mut parts, expr, args, translation)
+            }
+            Stmt::Macro(StmtMacro { attrs, mac, .. }) => {
+                expr_await(&mut parts, &Expr::Macro(ExprMacro { attrs: attrs.clone(), mac: mac.clone() }), args, translation)
             }
-            Stmt::Expr(Expr::Assign(ExprAssign { left, right: expr, .. }), ..) =>
+            Stmt::Expr(Expr::Assign(ExprAssign { left, right: expr, .. }), ..) => {
+                let assign = assign_result(left, &res, attrs);
                 match &**expr {
-                    Expr::Await(inner) => {
-                        expr_await(&mut parts, inner);
-                        quote!(
-                            #left = __res;
-                        ).to_tokens(parts.last_mut().unwrap());
+                    inner @ (Expr::Await(_) | Expr::Macro(_)) if expr_await(&mut parts, inner, args, translation) => {
+                        assign.to_tokens(parts.last_mut().unwrap());
                         true
                     }
-                    Expr::If(expr ) => {
-                        if expr_if(&mut parts, expr, false) {
-                            quote!(
-                                #left = __res;
-                            ).to_tokens(parts.last_mut().unwrap());
-                            true
-                        } else {
-                            false
-                        }
+                    Expr::If(expr ) if expr_if(&mut parts, expr, false, args, translation) => {
+                        assign.to_tokens(parts.last_mut().unwrap());
+                        true
                     }
-                    Expr::ForLoop(ExprForLoop { pat, expr, body, .. } ) => {
-                        if expr_for_loop(&mut parts, pat, expr, body, Some(left.to_token_stream())) {
-                            quote!(
-                                #left = __res;
-                            ).to_tokens(parts.last_mut().unwrap());
-                            true
-                        } else {
-                            false
-                        }
+                    Expr::ForLoop(ExprForLoop { pat, expr, body, .. } ) if expr_for_loop(&mut parts, pat, expr, body, Some(left.to_token_stream()), args, translation) => {
+                        assign.to_tokens(parts.last_mut().unwrap());
+                        true
                     }
                     _ => false
                 }
-            Stmt::Local(Local { pat, init: Some(LocalInit { expr, .. }), .. } ) =>
+            }
+            Stmt::Local(Local { pat, init: Some(LocalInit { expr, .. }), .. } ) => {
+                let binding = quote! {
+                    #(#attrs)*
+                    let #pat = #res;
+                };
                 match &**expr {
-                    Expr::Await(inner) => {
-                        expr_await(&mut parts, inner);
-                        quote! (
-                            let #pat = __res;
-                        ).to_tokens(parts.last_mut().unwrap());
+                    inner @ (Expr::Await(_) | Expr::Macro(_)) if expr_await(&mut parts, inner, args, translation) => {
+                        binding.to_tokens(parts.last_mut().unwrap());
                         true
                     }
-                    Expr::If(expr ) => {
-                        if expr_if(&mut parts, expr, false) {
-                            quote!(
-                                let #pat = __res;
-                            ).to_tokens(parts.last_mut().unwrap());
-                            true
-                        } else {
-                            false
-                        }
+                    Expr::If(expr ) if expr_if(&mut parts, expr, false, args, translation) => {
+                        binding.to_tokens(parts.last_mut().unwrap());
+                        true
                     }
-                    Expr::ForLoop(ExprForLoop { pat: for_pat, expr, body, .. } ) => {
-                        if expr_for_loop(&mut parts, for_pat, expr, body, Some(pat.to_token_stream())) {
-                            quote!(
-                                let #pat = __res;
-                            ).to_tokens(parts.last_mut().unwrap());
-                            true
-                        } else {
-                            false
-                        }
+                    Expr::ForLoop(ExprForLoop { pat: for_pat, expr, body, .. } ) if expr_for_loop(&mut parts, for_pat, expr, body, Some(pat.to_token_stream()), args, translation) => {
+                        binding.to_tokens(parts.last_mut().unwrap());
+                        true
                     }
                     _ => false
                 }
+            }
             Stmt::Expr(Expr::If(expr ), ..) => {
-                expr_if(&mut parts, expr, true)
+                expr_if(&mut parts, expr, true, args, translation)
             }
             Stmt::Expr(Expr::ForLoop(ExprForLoop { pat, expr, body, .. } ), ..) => {
-                expr_for_loop(&mut parts, pat, expr, body, None)
+                expr_for_loop(&mut parts, pat, expr, body, None, args, translation)
             }
             _ => false
         } {
+            *parts.last_mut().unwrap() = before;
             stmt.to_tokens(parts.last_mut().unwrap());
+            continue
+        }
+
+        let future = std::mem::take(&mut parts[split_at - 1]);
+        parts[split_at - 1] = before;
+        if attrs.is_empty() {
+            future.to_tokens(&mut parts[split_at - 1]);
+        } else {
+            future_with_attrs(future, attrs, &args.krate).to_tokens(&mut parts[split_at - 1]);
+        }
+
+        if translation.markers {
+            let continuation = parts.last_mut().unwrap();
+            let mut marked = segment_marker(stmt);
+            marked.extend(std::mem::take(continuation));
+            *continuation = marked;
         }
     }
     parts
 }
 
-fn expr_if(parts: &mut Vec<TokenStream>, expr: &ExprIf, return_unit: bool) -> bool {
-    let result = expr_if_inner(expr, return_unit);
+fn stmt_attrs(stmt: &Stmt) -> &[Attribute] {
+    match stmt {
+        Stmt::Local(Local { attrs, .. }) => attrs,
+        Stmt::Expr(Expr::Await(ExprAwait { attrs, .. }), _) => attrs,
+        Stmt::Expr(Expr::Macro(ExprMacro { attrs, .. }), _) => attrs,
+        Stmt::Macro(StmtMacro { attrs, .. }) => attrs,
+        Stmt::Expr(Expr::Assign(ExprAssign { attrs, .. }), _) => attrs,
+        Stmt::Expr(Expr::If(ExprIf { attrs, .. }), _) => attrs,
+        Stmt::Expr(Expr::ForLoop(ExprForLoop { attrs, .. }), _) => attrs,
+        _ => &[]
+    }
+}
+
+// Applies the attributes of a split statement to the future it awaits. A `#[cfg]` can't just remove the
+// future, as the continuation still chains on it, so a ready one for the same actor takes its place instead.
+fn future_with_attrs(future: TokenStream, attrs: &[Attribute], krate: &Path) -> TokenStream {
+    let fut = internal_ident("__fut");
+    let cfgs = attrs.iter()
+        .filter(|attr| attr.path().is_ident("cfg"))
+        .filter_map(|attr| attr.meta.require_list().ok().map(|list| &list.tokens))
+        .collect::<Vec<_>>();
+    let enabled = match cfgs.as_slice() {
+        [] => None,
+        [cfg] => Some(quote!(#cfg)),
+        cfgs => Some(quote!(all(#(#cfgs),*)))
+    };
+    let disabled = enabled.map(|enabled| quote! {
+        #[cfg(not(#enabled))]
+        let #fut = #krate::fut::wrap_future::<_, Self>(#krate::fut::ready(()));
+    });
+    quote! {{
+        #(#attrs)*
+        let #fut = { #future };
+        #disabled
+        #fut
+    }}
+}
+
+// `left = __res;` overwrites the copy of `left` the continuation moved in, which rustc reports as a
+// captured value never read. Reading the copy first keeps that from being reported.
+fn assign_result(left: &Expr, res: &Ident, attrs: &[Attribute]) -> TokenStream {
+    fn locals(left: &Expr) -> Vec<&Ident> {
+        match left {
+            Expr::Path(ExprPath { path, qself: None, .. }) => path.get_ident().into_iter().collect(),
+            Expr::Paren(ExprParen { expr, .. }) => locals(expr),
+            Expr::Tuple(ExprTuple { elems, .. }) => elems.iter().flat_map(locals).collect(),
+            _ => vec![]
+        }
+    }
+    let locals = locals(left);
+    let assign = quote! {
+        #(let _ = &#locals;)*
+        #left = #res;
+    };
+    if attrs.is_empty() {
+        assign
+    } else {
+        quote! {
+            #(#attrs)*
+            { #assign }
+        }
+    }
+}
+
+fn expr_if(parts: &mut Vec<TokenStream>, expr: &ExprIf, return_unit: bool, args: &HandlerArgs, translation: &Translation) -> bool {
+    let result = expr_if_inner(expr, return_unit, args, translation);
     if result.is_empty() {
         false
     } else {
@@ -232,32 +747,34 @@ fn expr_if(parts: &mut Vec<TokenStream>, expr: &ExprIf, return_unit: bool) -> bo
         true
     }
 }
-fn expr_if_inner(expr: &ExprIf, return_unit: bool) -> TokenStream {
+fn expr_if_inner(expr: &ExprIf, return_unit: bool, args: &HandlerArgs, translation: &Translation) -> TokenStream {
+    let krate = &args.krate;
     let ExprIf { cond, then_branch, else_branch, .. } = expr;
-    let then_parts = split_awaits(then_branch);
+    let then_parts = split_awaits(then_branch, args, translation);
 
     let mut token_stream = TokenStream::new();
 
-    let ret = if return_unit { Some(quote! { () }) } else { None };
+    // an empty return value ends the continuation with `;`, instead of a `()` tripping clippy::unused_unit
+    let ret = if return_unit { Some(quote! {}) } else { None };
 
     if then_parts.len() > 1 {
         let then_chain = build_future_chain(then_parts, false, ret.clone());
         quote!(
             if #cond {
-                Box::pin(#then_chain) as std::pin::Pin<Box<dyn actix::fut::future::ActorFuture<Self, Output=_>>>
+                Box::pin(#then_chain) as std::pin::Pin<Box<dyn #krate::fut::future::ActorFuture<Self, Output=_>>>
             }
         ).to_tokens(&mut token_stream);
         if else_branch.is_none() {
             quote!(
                 else {
-                    Box::pin(actix::fut::ready(()))
+                    Box::pin(#krate::fut::ready(()))
                 }
             ).to_tokens(&mut token_stream);
         } else {
             let else_expr = else_branch.as_ref().unwrap().1.as_ref();
             let awaited = match else_expr {
                 Expr::Block(ExprBlock { block, .. }) => {
-                    let else_parts = split_awaits(block);
+                    let else_parts = split_awaits(block, args, translation);
                     if else_parts.len() > 1 {
                         let else_chain = build_future_chain(else_parts, false, ret);
                         quote!(
@@ -271,7 +788,7 @@ fn expr_if_inner(expr: &ExprIf, return_unit: bool) -> TokenStream {
                     }
                 },
                 Expr::If(if_expr) => {
-                    let else_parts = expr_if_inner(if_expr, return_unit);
+                    let else_parts = expr_if_inner(if_expr, return_unit, args, translation);
                     if !else_parts.is_empty() {
                         // chained else if(s) have awaits
                         quote!(
@@ -288,13 +805,13 @@ fn expr_if_inner(expr: &ExprIf, return_unit: bool) -> TokenStream {
                 if return_unit {
                     quote!(
                         else {
-                            Box::pin(actix::fut::ready({ #else_expr; }))
+                            Box::pin(#krate::fut::ready({ #else_expr; }))
                         }
                     )
                 } else {
                     quote!(
                         else {
-                            Box::pin(actix::fut::ready(#else_expr))
+                            Box::pin(#krate::fut::ready(#else_expr))
                         }
                     )
                 }.to_tokens(&mut token_stream);
@@ -303,21 +820,21 @@ fn expr_if_inner(expr: &ExprIf, return_unit: bool) -> TokenStream {
     } else if else_branch.is_some() {
         match else_branch.as_ref().unwrap().1.as_ref() {
             Expr::Block(ExprBlock { block, .. }) => {
-                let else_parts = split_awaits(block);
+                let else_parts = split_awaits(block, args, translation);
                 if else_parts.len() > 1 {
                     let else_chain = build_future_chain(else_parts, false, ret);
-                    non_awaited_if_expr_for_else(return_unit, cond, then_branch, &mut token_stream);
+                    non_awaited_if_expr_for_else(return_unit, cond, then_branch, &mut token_stream, krate);
                     quote!(
                         else {
-                            Box::pin(#else_chain) as std::pin::Pin<Box<dyn actix::fut::future::ActorFuture<Self, Output=_>>>
+                            Box::pin(#else_chain) as std::pin::Pin<Box<dyn #krate::fut::future::ActorFuture<Self, Output=_>>>
                         }
                     ).to_tokens(&mut token_stream);
                 }
             }
             Expr::If(if_expr) => {
-                let else_parts = expr_if_inner(if_expr, return_unit);
+                let else_parts = expr_if_inner(if_expr, return_unit, args, translation);
                 if !else_parts.is_empty() {
-                    non_awaited_if_expr_for_else(return_unit, cond, then_branch, &mut token_stream);
+                    non_awaited_if_expr_for_else(return_unit, cond, then_branch, &mut token_stream, krate);
                     // chained else if(s) have awaits
                     quote!(
                         else #else_parts
@@ -330,48 +847,131 @@ fn expr_if_inner(expr: &ExprIf, return_unit: bool) -> TokenStream {
     token_stream
 }
 
-fn non_awaited_if_expr_for_else(return_unit: bool, cond: &Box<Expr>, then_branch: &Block, mut token_stream: &mut TokenStream) {
+fn non_awaited_if_expr_for_else(return_unit: bool, cond: &Expr, then_branch: &Block, token_stream: &mut TokenStream, krate: &Path) {
     if return_unit {
         quote!(
             if #cond {
-                Box::pin(actix::fut::ready({ #then_branch; }))
+                Box::pin(#krate::fut::ready({ #then_branch; }))
             }
-        ).to_tokens(&mut token_stream);
+        ).to_tokens(token_stream);
     } else {
         quote!(
             if #cond {
-                Box::pin(actix::fut::ready(#then_branch))
+                Box::pin(#krate::fut::ready(#then_branch))
             }
-        ).to_tokens(&mut token_stream);
+        ).to_tokens(token_stream);
     }
 }
 
-fn expr_await(parts: &mut Vec<TokenStream>, expr: &ExprAwait) {
-    let base = &*expr.base;
-    quote!(
-        actix::fut::wrap_future::<_, Self>(#base)
-    ).to_tokens(parts.last_mut().unwrap());
+// Takes both `fut.await` and `actor_await!(fut)`, telling whether the expression was awaiting at all
+fn expr_await(parts: &mut Vec<TokenStream>, expr: &Expr, args: &HandlerArgs, translation: &Translation) -> bool {
+    let krate = &args.krate;
+    let future = match expr {
+        Expr::Await(ExprAwait { base, .. }) if is_inline_handler_call(base) => inline_handler_call(base)
+            .unwrap_or_else(|e| e.to_compile_error()),
+        Expr::Await(ExprAwait { base, .. }) if is_actor_future(base, translation) => quote!(#base),
+        Expr::Await(ExprAwait { base, .. }) if is_self_call(base) => self_call_future(base, krate),
+        Expr::Await(ExprAwait { base, .. }) => quote!(#krate::fut::wrap_future::<_, Self>(#base)),
+        Expr::Macro(ExprMacro { mac, .. }) if is_actor_await(mac) => match mac.parse_body::<Expr>() {
+            Ok(base @ (Expr::MethodCall(_) | Expr::Call(_) | Expr::Path(_) | Expr::Field(_) | Expr::Paren(_) | Expr::Macro(_))) => quote!(#base),
+            Ok(base) => quote!((#base)),
+            Err(e) => e.to_compile_error()
+        },
+        _ => return false
+    };
+    future.to_tokens(parts.last_mut().unwrap());
     parts.push(TokenStream::new());
+    true
+}
+
+// `fut.into_actor(self)` already is an actor future, and so is what the #[actor_fn]s expanded along return when
+// called right on `self`. Any other method of the actor is taken to return a plain future.
+fn is_actor_future(base: &Expr, translation: &Translation) -> bool {
+    match base {
+        Expr::MethodCall(ExprMethodCall { method, .. }) if method == "into_actor" => true,
+        Expr::MethodCall(ExprMethodCall { method, .. }) => translation.actor_fns.contains(method) && is_self_call(base),
+        _ => false
+    }
+}
+
+fn is_self_call(base: &Expr) -> bool {
+    matches!(base, Expr::MethodCall(ExprMethodCall { receiver, .. }) if matches!(&**receiver,
+        Expr::Path(ExprPath { path, qself: None, .. }) if path.is_ident("__self")))
+}
+
+fn is_inline_handler_call(base: &Expr) -> bool {
+    matches!(base, Expr::MethodCall(ExprMethodCall { method, .. }) if method == "handle_inline") && is_self_call(base)
+}
+
+// `self.handle_inline(Msg(..), ctx)` calls the inherent fn generated along the Handler impl for `Msg`, which is told
+// by the message expression or a turbofish, as in `self.handle_inline::<Msg>(msg, ctx)`
+fn inline_handler_call(base: &Expr) -> Result<TokenStream> {
+    let Expr::MethodCall(call) = base else {
+        return Err(Error::new(base.span(), "#[async_handler] expected a call to `handle_inline`"))
+    };
+    let message = match (&call.turbofish, call.args.first()) {
+        (Some(turbofish), _) => match turbofish.args.first() {
+            Some(GenericArgument::Type(Type::Path(path))) => path.path.segments.last(),
+            _ => None
+        },
+        (None, Some(Expr::Call(ExprCall { func, .. }))) => match &**func {
+            Expr::Path(path) => path.path.segments.last(),
+            _ => None
+        },
+        (None, Some(Expr::Struct(ExprStruct { path, .. }) | Expr::Path(ExprPath { path, .. }))) => path.segments.last(),
+        _ => None
+    };
+    // a lowercase path is a variable rather than a unit struct
+    let Some(message) = message.filter(|message| message.ident.to_string().starts_with(char::is_uppercase)) else {
+        return Err(Error::new(call.span(),
+            "#[async_handler] `handle_inline` can't tell the message type, name it as in `self.handle_inline::<Msg>(msg, ctx)`"))
+    };
+    if call.args.len() != 2 {
+        return Err(Error::new(call.args.span(), "#[async_handler] `handle_inline` takes the message and the context"))
+    }
+
+    let receiver = &call.receiver;
+    let ident = inline_handler_ident(&message.ident);
+    let args = &call.args;
+    Ok(quote!(#receiver.#ident(#args)))
+}
+
+// `actor_await!` only marks what to chain without wrapping, used anywhere else it expands to an error
+fn is_actor_await(mac: &Macro) -> bool {
+    mac.path.segments.last().map(|last| last.ident == "actor_await").unwrap_or(false)
+}
+
+pub fn actor_await_impl() -> TokenStream {
+    Error::new(Span::call_site(),
+        "actor_await! can only be awaited as a statement of #[async_handler] and #[actor_fn] bodies, as in `let x = actor_await!(fut);`")
+        .to_compile_error()
 }
 
-fn expr_for_loop(parts: &mut Vec<TokenStream>, pat: &Box<Pat>, expr: &Box<Expr>, body: &Block, acc: Option<TokenStream>) -> bool {
-    let body_parts = split_awaits(body);
+fn expr_for_loop(parts: &mut Vec<TokenStream>, pat: &Pat, expr: &Expr, body: &Block, acc: Option<TokenStream>, args: &HandlerArgs, translation: &Translation) -> bool {
+    let body_parts = split_awaits(body, args, translation);
     if body_parts.len() > 1 {
 
-        let unpack_acc_prefix = match acc.clone() {
-            Some(a) => quote! { let mut #a = __acc; },
+        let (acc_param, this, ctx) = (internal_ident("__acc"), internal_ident("__self"), internal_ident("__ctx"));
+        let unpack_acc_prefix = match &acc {
+            Some(a) => {
+                let binding = acc_binding(a);
+                quote! { #[allow(unused_mut)] let #binding = #acc_param; }
+            }
             _ => quote! {}
         };
 
-        let acc = acc.or(Some(quote! { () }));
-        let body = build_future_chain(body_parts, false, acc.clone());
+        // a `()` returned by the body would trip clippy::unused_unit
+        let body = build_future_chain(body_parts, false, Some(acc.clone().unwrap_or_default()));
+        let acc = acc.unwrap_or(quote! { () });
 
+        let krate = &args.krate;
+        let stream = iter_stream(expr, krate);
         quote! {
-            use actix::ActorStreamExt;
-            actix::fut::wrap_stream(futures::stream::iter(IntoIterator::into_iter(#expr)))
-                .fold(#acc, move |__acc, #pat, __self, __ctx| {
+            use #krate::ActorStreamExt;
+            #krate::fut::wrap_stream(#stream)
+                .fold(#acc, move |#acc_param, #pat, #this, #ctx| {
                     #unpack_acc_prefix;
-                    Box::pin(#body) as std::pin::Pin<Box<dyn actix::fut::future::ActorFuture<Self, Output=_>>>
+                    Box::pin(#body) as std::pin::Pin<Box<dyn #krate::fut::future::ActorFuture<Self, Output=_>>>
                 })
         }.to_tokens(parts.last_mut().unwrap());
         parts.push(TokenStream::new());
@@ -381,35 +981,509 @@ fn expr_for_loop(parts: &mut Vec<TokenStream>, pat: &Box<Pat>, expr: &Box<Expr>,
     }
 }
 
-struct RenameParams(String);
+// Binds each variable of the accumulator, `i` or `(i, j)`, mutably for the loop body
+fn acc_binding(acc: &TokenStream) -> TokenStream {
+    fn make_mut(pat: &mut Pat) {
+        match pat {
+            Pat::Ident(ident) => ident.mutability = Some(Default::default()),
+            Pat::Paren(PatParen { pat, .. }) => make_mut(pat),
+            Pat::Tuple(PatTuple { elems, .. }) => elems.iter_mut().for_each(make_mut),
+            _ => ()
+        }
+    }
+    match Pat::parse_single.parse2(acc.clone()) {
+        Ok(mut pat) => {
+            make_mut(&mut pat);
+            pat.to_token_stream()
+        }
+        Err(_) => quote! { mut #acc }
+    }
+}
+
+// A `Stream` over the loop's iterable, declared in place so user crates don't need `futures` to
+// get one. Boxing the iterator keeps the stream `Unpin` whatever the iterator is.
+// The iterator is taken before declaring the stream type, so the user's expression can't see it
+// Methods of the actor may return a plain future as well as an actor future, as `#[actor_fn]`s do, which isn't
+// known from a standalone handler. The autoref on the wrapper picks the actor future as is when it is one, and
+// wraps it otherwise.
+fn self_call_future(base: &Expr, krate: &Path) -> TokenStream {
+    let (wrapper, as_actor_future, as_future, method) = (internal_ident("__SelfCall"),
+        internal_ident("__SelfCallActorFuture"), internal_ident("__SelfCallFuture"), internal_ident("__actor_future"));
+    quote! {{
+        struct #wrapper<F, A>(std::cell::Cell<Option<F>>, std::marker::PhantomData<A>);
+        trait #as_actor_future {
+            type Future;
+            fn #method(&self) -> Self::Future;
+        }
+        impl<F: #krate::ActorFuture<A>, A: #krate::Actor> #as_actor_future for &#wrapper<F, A> {
+            type Future = F;
+            fn #method(&self) -> F {
+                self.0.take().unwrap()
+            }
+        }
+        trait #as_future {
+            type Future;
+            fn #method(&self) -> Self::Future;
+        }
+        impl<F: std::future::Future, A: #krate::Actor> #as_future for #wrapper<F, A> {
+            type Future = #krate::fut::future::FutureWrap<F, A>;
+            fn #method(&self) -> Self::Future {
+                #krate::fut::wrap_future(self.0.take().unwrap())
+            }
+        }
+        (&&#wrapper(std::cell::Cell::new(Some(#base)), std::marker::PhantomData::<Self>)).#method()
+    }}
+}
 
-impl Fold for RenameParams {
-    fn fold_ident(&mut self, i: Ident) -> Ident {
-        if i == "self" {
-            Ident::new("__self", i.span())
-        } else if i == self.0 {
-            Ident::new("__ctx", i.span())
+fn iter_stream(expr: &Expr, krate: &Path) -> TokenStream {
+    let (iter, stream) = (internal_ident("__iter"), internal_ident("__IterStream"));
+    quote! {{
+        let #iter = IntoIterator::into_iter(#expr);
+        {
+            struct #stream<I>(Box<I>);
+            impl<I: Iterator> #krate::prelude::Stream for #stream<I> {
+                type Item = I::Item;
+                fn poll_next(self: std::pin::Pin<&mut Self>, _: &mut std::task::Context<'_>) -> std::task::Poll<Option<I::Item>> {
+                    std::task::Poll::Ready(self.get_mut().0.next())
+                }
+            }
+            #stream(Box::new(#iter))
+        }
+    }}
+}
+
+// References taken from `self` or `ctx` can't be kept across an await, as the code after it runs
+// in a continuation that gets the actor and its context handed in again as `__self` and `__ctx`.
+// Only references and the accessors in `BORROWING_METHODS` are told apart, see `borrows_state`.
+fn check_state_borrows(block: &Block, actor: &str, ctx_ident: Option<&str>, mut live: Vec<Ident>) -> Result<()> {
+    let mut held: Vec<Ident> = vec![];
+    for stmt in &block.stmts {
+        for borrow in &held {
+            let usage = find_usage(borrow, |usages| match stmt {
+                Stmt::Local(Local { init: Some(init), .. }) => {
+                    usages.fold_expr((*init.expr).clone());
+                    if let Some((_, diverge)) = &init.diverge {
+                        usages.fold_expr((**diverge).clone());
+                    }
+                }
+                Stmt::Local(_) => (),
+                _ => { usages.fold_stmt(stmt.clone()); }
+            });
+            if let Some(usage) = usage {
+                let mut error = Error::new(usage.span(), format!(
+                    "`{}` borrows the actor state across an await. Every await resumes with a fresh borrow of \
+                     `{}` and `{}`, so re-borrow the actor state after the await instead", usage, actor, ctx_ident.unwrap_or("ctx")));
+                error.combine(Error::new(borrow.span(), format!("`{}` is borrowed here", borrow)));
+                return Err(error)
+            }
+        }
+
+        if let Some(expr) = awaiting_expr(stmt) {
+            for nested in nested_blocks(expr) {
+                check_state_borrows(nested, actor, ctx_ident, live.clone())?;
+            }
+        }
+
+        if let Stmt::Local(Local { pat, init, .. }) = stmt {
+            let bound = pat_idents(pat);
+            live.retain(|i| !bound.contains(i));
+            held.retain(|i| !bound.contains(i));
+            if init.as_ref().map(|init| borrows_state(&init.expr, actor, ctx_ident)).unwrap_or(false) {
+                live.extend(bound);
+            }
+        }
+
+        if split_awaits(&Block { brace_token: block.brace_token, stmts: vec![stmt.clone()] }, &HandlerArgs::default(), &Translation::default()).len() > 1 {
+            held.append(&mut live);
+        }
+    }
+    Ok(())
+}
+
+fn awaiting_expr(stmt: &Stmt) -> Option<&Expr> {
+    match stmt {
+        Stmt::Expr(Expr::Assign(ExprAssign { right: expr, .. }), _) => Some(expr),
+        Stmt::Local(Local { init: Some(LocalInit { expr, .. }), .. }) => Some(expr),
+        Stmt::Expr(expr, _) => Some(expr),
+        _ => None
+    }
+}
+
+fn nested_blocks(expr: &Expr) -> Vec<&Block> {
+    match expr {
+        Expr::If(ExprIf { then_branch, else_branch, .. }) => {
+            let mut blocks = vec![then_branch];
+            match else_branch.as_ref().map(|e| &*e.1) {
+                Some(Expr::Block(ExprBlock { block, .. })) => blocks.push(block),
+                Some(else_if @ Expr::If(_)) => blocks.append(&mut nested_blocks(else_if)),
+                _ => ()
+            }
+            blocks
+        }
+        Expr::ForLoop(ExprForLoop { body, .. }) => vec![body],
+        _ => vec![]
+    }
+}
+
+fn borrows_state(expr: &Expr, actor: &str, ctx_ident: Option<&str>) -> bool {
+    fn is_state(expr: &Expr, actor: &str, ctx_ident: Option<&str>) -> bool {
+        match expr {
+            Expr::Field(ExprField { base, .. }) => is_state(base, actor, ctx_ident),
+            Expr::Index(ExprIndex { expr, .. }) => is_state(expr, actor, ctx_ident),
+            Expr::MethodCall(ExprMethodCall { receiver, .. }) => is_state(receiver, actor, ctx_ident),
+            Expr::Paren(ExprParen { expr, .. }) => is_state(expr, actor, ctx_ident),
+            Expr::Path(ExprPath { path, qself: None, .. }) =>
+                path.get_ident().map(|i| i == actor || ctx_ident.is_some_and(|ctx| i == ctx)).unwrap_or(false),
+            _ => false
+        }
+    }
+    match expr {
+        Expr::Reference(ExprReference { expr, .. }) => is_state(expr, actor, ctx_ident),
+        Expr::Paren(ExprParen { expr, .. }) => borrows_state(expr, actor, ctx_ident),
+        // `self.cache.get_mut(&key)`, with the borrow possibly unwrapped afterwards
+        Expr::MethodCall(ExprMethodCall { receiver, method, .. }) => match method.to_string().as_str() {
+            "unwrap" | "expect" => borrows_state(receiver, actor, ctx_ident),
+            method => BORROWING_METHODS.contains(&method) && is_state(receiver, actor, ctx_ident)
+        },
+        _ => false
+    }
+}
+
+// Accessors of the std collections and wrappers returning a borrow of their receiver. Any other method may as
+// well return an owned value, as `clone` does, so it's not taken for a borrow.
+const BORROWING_METHODS: &[&str] = &[
+    "get", "get_mut", "first", "first_mut", "last", "last_mut", "iter", "iter_mut", "keys", "values", "values_mut",
+    "entry", "as_ref", "as_mut", "as_deref", "as_deref_mut", "as_str", "as_slice", "as_mut_slice", "borrow", "borrow_mut",
+];
+
+fn pat_idents(pat: &Pat) -> Vec<Ident> {
+    match pat {
+        Pat::Ident(PatIdent { ident, subpat, .. }) => std::iter::once(ident.clone())
+            .chain(subpat.iter().flat_map(|(_, pat)| pat_idents(pat)))
+            .collect(),
+        Pat::Type(PatType { pat, .. }) => pat_idents(pat),
+        Pat::Reference(PatReference { pat, .. }) => pat_idents(pat),
+        Pat::Paren(PatParen { pat, .. }) => pat_idents(pat),
+        Pat::Tuple(PatTuple { elems, .. }) => elems.iter().flat_map(pat_idents).collect(),
+        Pat::TupleStruct(PatTupleStruct { elems, .. }) => elems.iter().flat_map(pat_idents).collect(),
+        Pat::Struct(PatStruct { fields, .. }) => fields.iter().flat_map(|f| pat_idents(&f.pat)).collect(),
+        Pat::Slice(PatSlice { elems, .. }) => elems.iter().flat_map(pat_idents).collect(),
+        // every alternative binds the same names
+        Pat::Or(PatOr { cases, .. }) => cases.first().map(pat_idents).unwrap_or_default(),
+        _ => vec![]
+    }
+}
+
+// The first use of the binding within what `fold` goes through. It's looked up as the actor would be renamed,
+// so fields, paths and uses past a binding shadowing it don't count.
+fn find_usage(ident: &Ident, fold: impl FnOnce(&mut RenameParams)) -> Option<Ident> {
+    let mut usages = RenameParams { actor: ident.to_string(), ..RenameParams::new(None) };
+    fold(&mut usages);
+    usages.renames.into_iter().next()
+}
+
+// Renames `self` and the ctx binding, if any, to the continuation parameters. Only paths naming them
+// are renamed: fields, enum variants and nested items are left alone, and so is anything past a
+// binding that shadows ctx.
+struct RenameParams {
+    /// `self`, unless the actor is bound to another name, as in `actor_async!(|act, ctx| ..)`
+    actor: String,
+    ctx: Option<String>,
+    /// params bound again in the current scope, which are left as they are
+    shadowed: Vec<String>,
+    ctx_renamed: bool,
+    /// the params renamed so far, as they were written
+    renames: Vec<Ident>,
+}
+
+impl RenameParams {
+    fn new(ctx: Option<String>) -> Self {
+        RenameParams { actor: "self".to_string(), ctx, shadowed: vec![], ctx_renamed: false, renames: vec![] }
+    }
+
+    fn is_actor(&self, name: &str) -> bool {
+        name == self.actor && !self.shadowed.iter().any(|shadowed| shadowed == name)
+    }
+
+    fn is_ctx(&self, name: &str) -> bool {
+        self.ctx.as_ref().is_some_and(|ctx| name == ctx) && !self.shadowed.iter().any(|shadowed| shadowed == name)
+    }
+
+    // The params among the bindings of the pattern
+    fn binds(&self, pat: &Pat) -> Vec<String> {
+        pat_idents(pat).into_iter()
+            .map(|ident| ident.to_string())
+            .filter(|name| *name == self.actor || self.ctx.as_ref() == Some(name))
+            .collect()
+    }
+
+    fn renamed(&mut self, name: &str) -> Option<&'static str> {
+        if self.is_actor(name) {
+            Some("__self")
+        } else if self.is_ctx(name) {
+            self.ctx_renamed = true;
+            Some("__ctx")
         } else {
-            i
+            None
+        }
+    }
+
+    fn rename(&mut self, ident: &Ident) -> Option<Ident> {
+        let renamed = self.renamed(&ident.to_string())?;
+        self.renames.push(ident.clone());
+        Some(Ident::new(renamed, ident.span().resolved_at(Span::mixed_site())))
+    }
+
+    fn shadowing<T>(&mut self, shadows: Vec<String>, fold: impl FnOnce(&mut Self) -> T) -> T {
+        let outer = self.shadowed.len();
+        self.shadowed.extend(shadows);
+        let folded = fold(self);
+        self.shadowed.truncate(outer);
+        folded
+    }
+}
+
+impl Fold for RenameParams {
+    fn fold_expr_path(&mut self, mut i: ExprPath) -> ExprPath {
+        if i.qself.is_none() {
+            if let Some(renamed) = i.path.get_ident().and_then(|ident| self.rename(ident)) {
+                i.path = renamed.into();
+            }
+        }
+        i
+    }
+
+    // `Msg { ctx }` has to become `Msg { ctx: __ctx }`
+    fn fold_field_value(&mut self, mut i: FieldValue) -> FieldValue {
+        if i.colon_token.is_none() {
+            if let Member::Named(member) = &i.member {
+                if self.is_actor(&member.to_string()) || self.is_ctx(&member.to_string()) {
+                    i.colon_token = Some(Default::default());
+                }
+            }
         }
+        i.expr = self.fold_expr(i.expr);
+        i
+    }
+
+    fn fold_item(&mut self, i: Item) -> Item {
+        // `self` and ctx aren't reachable from nested items
+        i
+    }
+
+    fn fold_block(&mut self, i: Block) -> Block {
+        self.shadowing(vec![], |this| Block {
+            brace_token: i.brace_token,
+            stmts: i.stmts.into_iter().map(|stmt| {
+                let shadows = match &stmt {
+                    Stmt::Local(Local { pat, .. }) => this.binds(pat),
+                    _ => vec![]
+                };
+                let folded = this.fold_stmt(stmt);
+                this.shadowed.extend(shadows);
+                folded
+            }).collect(),
+        })
+    }
+
+    fn fold_expr_closure(&mut self, mut i: ExprClosure) -> ExprClosure {
+        let shadows = i.inputs.iter().flat_map(|pat| self.binds(pat)).collect();
+        let body = *i.body;
+        i.body = Box::new(self.shadowing(shadows, |this| this.fold_expr(body)));
+        i
+    }
+
+    fn fold_arm(&mut self, mut i: Arm) -> Arm {
+        let shadows = self.binds(&i.pat);
+        self.shadowing(shadows, |this| {
+            i.guard = i.guard.map(|(if_token, guard)| (if_token, Box::new(this.fold_expr(*guard))));
+            *i.body = this.fold_expr(*i.body);
+            i
+        })
+    }
+
+    fn fold_expr_if(&mut self, mut i: ExprIf) -> ExprIf {
+        let shadows = match &*i.cond {
+            Expr::Let(ExprLet { pat, .. }) => self.binds(pat),
+            _ => vec![]
+        };
+        i.cond = Box::new(self.fold_expr(*i.cond));
+        let then_branch = i.then_branch;
+        i.then_branch = self.shadowing(shadows, |this| this.fold_block(then_branch));
+        i.else_branch = i.else_branch.map(|(else_token, branch)| (else_token, Box::new(self.fold_expr(*branch))));
+        i
+    }
+
+    fn fold_expr_while(&mut self, mut i: ExprWhile) -> ExprWhile {
+        let shadows = match &*i.cond {
+            Expr::Let(ExprLet { pat, .. }) => self.binds(pat),
+            _ => vec![]
+        };
+        i.cond = Box::new(self.fold_expr(*i.cond));
+        let body = i.body;
+        i.body = self.shadowing(shadows, |this| this.fold_block(body));
+        i
+    }
+
+    fn fold_expr_for_loop(&mut self, mut i: ExprForLoop) -> ExprForLoop {
+        let shadows = self.binds(&i.pat);
+        i.expr = Box::new(self.fold_expr(*i.expr));
+        let body = i.body;
+        i.body = self.shadowing(shadows, |this| this.fold_block(body));
+        i
     }
 
-    fn fold_macro(&mut self, i: Macro) -> Macro {
-        let mut output = i.clone();
-        output.tokens = TokenStream::new();
-        for token in i.tokens {
-            match token {
-                TokenTree::Ident(ident) => {
-                    TokenTree::Ident(self.fold_ident(ident)).to_tokens(&mut output.tokens);
+    fn fold_macro(&mut self, mut i: Macro) -> Macro {
+        let name = i.path.segments.last().map(|segment| segment.ident.to_string());
+        i.tokens = self.rename_macro_args(name.as_deref(), i.tokens);
+        i
+    }
+}
+
+// Position of the format string in the arguments of the std and log formatting macros
+fn format_arg(name: &str) -> Option<usize> {
+    match name {
+        "format" | "format_args" | "print" | "println" | "eprint" | "eprintln" | "panic" | "unreachable" | "todo"
+            | "unimplemented" | "trace" | "debug" | "info" | "warn" | "error" => Some(0),
+        "write" | "writeln" | "assert" | "debug_assert" | "log" => Some(1),
+        "assert_eq" | "assert_ne" | "debug_assert_eq" | "debug_assert_ne" => Some(2),
+        _ => None
+    }
+}
+
+impl RenameParams {
+    // Macro arguments are just tokens, so this goes through every nested group
+    fn rename_tokens(&mut self, tokens: TokenStream) -> TokenStream {
+        let mut output = TokenStream::new();
+        // idents after `.` or `::` are fields, methods or path segments, unlike those after a `..` range
+        let mut previous = [' ', ' '];
+        // name of the nested macro whose arguments come next, as in `format!(..)`
+        let (mut last_ident, mut macro_name): (Option<String>, Option<String>) = (None, None);
+        for token in tokens {
+            let in_path = (previous[1] == '.' && previous[0] != '.') || previous == [':', ':'];
+            match &token {
+                TokenTree::Ident(ident) if !in_path => {
+                    self.rename(ident).unwrap_or_else(|| ident.clone()).to_tokens(&mut output);
+                }
+                TokenTree::Group(group) => {
+                    let stream = match macro_name.take() {
+                        Some(name) if previous[1] == '!' => self.rename_macro_args(Some(&name), group.stream()),
+                        _ => self.rename_tokens(group.stream())
+                    };
+                    let mut renamed = Group::new(group.delimiter(), stream);
+                    renamed.set_span(group.span());
+                    renamed.to_tokens(&mut output);
                 }
                 other => {
-                    other.to_tokens(&mut output.tokens)
+                    other.to_tokens(&mut output)
+                }
+            }
+            macro_name = match &token {
+                TokenTree::Punct(p) if p.as_char() == '!' => last_ident.take(),
+                _ => None
+            };
+            last_ident = if let TokenTree::Ident(ident) = &token { Some(ident.to_string()) } else { None };
+            let current = if let TokenTree::Punct(p) = &token { p.as_char() } else { ' ' };
+            previous = [previous[1], current];
+        }
+        output
+    }
+
+    // Only the format string of the known formatting macros is looked into, other literals are
+    // left as they are. Leading `key: value` arguments, as the `target:` of the log macros, are
+    // skipped when counting. Renamed captures are passed on as named arguments, as the string
+    // keeps its own span for the other captures to resolve.
+    fn rename_macro_args(&mut self, name: Option<&str>, tokens: TokenStream) -> TokenStream {
+        let Some(format_arg) = name.and_then(format_arg) else {
+            return self.rename_tokens(tokens)
+        };
+
+        let mut args = vec![vec![]];
+        for token in tokens {
+            let is_comma = matches!(&token, TokenTree::Punct(p) if p.as_char() == ',');
+            args.last_mut().unwrap().push(token);
+            if is_comma {
+                args.push(vec![]);
+            }
+        }
+
+        let is_keyed = |arg: &[TokenTree]| matches!(arg,
+            [TokenTree::Ident(_), TokenTree::Punct(p), ..] if p.as_char() == ':' && p.spacing() == Spacing::Alone);
+        let keyed = args.iter().take_while(|arg| is_keyed(arg)).count();
+        let named: Vec<String> = args.iter().filter_map(|arg| match arg.as_slice() {
+            [TokenTree::Ident(name), TokenTree::Punct(p), ..] if p.as_char() == '=' && p.spacing() == Spacing::Alone =>
+                Some(name.to_string()),
+            _ => None
+        }).collect();
+        let trailing_comma = args.last().is_some_and(|arg| arg.is_empty());
+
+        let mut output = TokenStream::new();
+        let mut captures = vec![];
+        for (index, arg) in args.into_iter().enumerate() {
+            match arg.as_slice() {
+                [TokenTree::Literal(literal), rest @ ..] if index == keyed + format_arg
+                        && rest.iter().all(|t| matches!(t, TokenTree::Punct(p) if p.as_char() == ',')) => {
+                    let (renamed, renamed_captures) = self.rename_format_args(literal, &named);
+                    renamed.to_tokens(&mut output);
+                    output.extend(rest.iter().cloned());
+                    captures = renamed_captures;
                 }
+                _ => output.extend(self.rename_tokens(arg.into_iter().collect()))
             }
         }
+
+        if !captures.is_empty() {
+            let separator = (!trailing_comma).then(|| quote!(,));
+            output.extend(quote!(#separator #(#captures = #captures),*));
+        }
         output
     }
 
+    // Inline format args, as in `"{self:?}"` or `"{ctx:p}"`, name the params from inside the string.
+    // Returns the string along with the renamed params it captures, which aren't in scope for it.
+    fn rename_format_args(&mut self, literal: &Literal, named: &[String]) -> (TokenTree, Vec<Ident>) {
+        let unchanged = TokenTree::Literal(literal.clone());
+        let Ok(string) = syn::parse2::<LitStr>(unchanged.clone().into()) else {
+            return (unchanged, vec![])
+        };
+
+        let value = string.value();
+        let mut captures: Vec<Ident> = vec![];
+        let mut renamed = String::with_capacity(value.len());
+        let mut rest = value.as_str();
+        while let Some(start) = rest.find(['{', '}']) {
+            let (before, from) = rest.split_at(start);
+            renamed.push_str(before);
+            let skipped = if from.starts_with("{{") || from.starts_with("}}") { 2 } else { 1 };
+            renamed.push_str(&from[..skipped]);
+            rest = &from[skipped..];
+            if skipped == 2 || from.starts_with('}') {
+                continue
+            }
+
+            let name_len = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            let (name, after) = rest.split_at(name_len);
+            let is_capture = after.starts_with(['}', ':']) && !named.iter().any(|named| named == name);
+            match is_capture.then(|| self.renamed(name)).flatten() {
+                Some(capture) => {
+                    self.renames.push(Ident::new(name, literal.span()));
+                    renamed.push_str(capture);
+                    if !captures.iter().any(|c| c == capture) {
+                        captures.push(Ident::new(capture, literal.span().resolved_at(Span::mixed_site())));
+                    }
+                }
+                None => renamed.push_str(name)
+            }
+            rest = after;
+        }
+        renamed.push_str(rest);
+
+        if captures.is_empty() {
+            (unchanged, captures)
+        } else {
+            (TokenTree::Literal(LitStr::new(&renamed, literal.span()).token()), captures)
+        }
+    }
 }
 
 #[cfg(test)]
@@ -418,18 +1492,261 @@ mod tests {
 
     #[test]
     fn test_requires_handler_impl_fails() {
-        assert!(async_handler_inner(false, quote! {
+        assert!(async_handler_inner(&HandlerArgs { is_atomic: false, ..Default::default() }, &Translation::default(), quote! {
             struct AnActor {}
         }).is_err());
     }
 
     #[test]
     fn test_requires_handler_impl() {
-        assert!(async_handler_inner(false, quote! {
+        assert!(async_handler_inner(&HandlerArgs { is_atomic: false, ..Default::default() }, &Translation::default(), quote! {
             impl Handler<T> for AnActor {}
         }).is_ok());
     }
 
+    #[test]
+    fn test_requires_handler_impl_by_last_segment() {
+        let args = HandlerArgs::default();
+        assert!(async_handler_inner(&args, &Translation::default(), quote! { impl actix::Handler<T> for AnActor {} }).is_ok());
+        assert!(async_handler_inner(&args, &Translation::default(), quote! { impl actix::prelude::Handler<T> for AnActor {} }).is_ok());
+        assert!(async_handler_inner(&args, &Translation::default(), quote! { impl<M: Msg> Handler<M> for Router {} }).is_ok());
+        assert!(async_handler_inner(&args, &Translation::default(), quote! { impl Handler::Fallible<T> for AnActor {} }).is_err());
+        assert!(async_handler_inner(&args, &Translation::default(), quote! { impl Handler for AnActor {} }).is_err());
+    }
+
+    #[test]
+    fn test_requires_handler_trait_override() {
+        let args = HandlerArgs { handler_trait: parse_quote!(Handles), ..Default::default() };
+        assert!(async_handler_inner(&args, &Translation::default(), quote! { impl Handles<T> for AnActor {} }).is_ok());
+        assert!(async_handler_inner(&args, &Translation::default(), quote! { impl Handler<T> for AnActor {} }).is_err());
+    }
+
+    #[test]
+    fn test_requires_a_result_type() {
+        let error = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
+            impl Handler<Ping> for AnActor {
+                async fn handle(&mut self, msg: Ping, ctx: &mut Context<Self>) -> Self::Result {
+                    self.delegate.send(msg).await
+                }
+            }
+        }).expect_err("").to_string();
+        assert_eq!(error, "#[async_handler] needs either `type Result` in the impl or the result type as the return type of `handle`");
+
+        assert!(async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
+            impl Handler<Ping> for AnActor {
+                async fn handle(&mut self, msg: Ping, ctx: &mut Context<Self>) {}
+            }
+        }).is_err());
+    }
+
+    #[test]
+    fn test_rejects_atomic_response_with_non_atomic() {
+        let error = async_handler_inner(&HandlerArgs { is_atomic: false, ..Default::default() }, &Translation::default(), quote! {
+            impl Handler<Ping> for AnActor {
+                type Result = actix::AtomicResponse<Self, u64>;
+                async fn handle(&mut self, msg: Ping, ctx: &mut Self::Context) -> Self::Result {
+                    self.delegate.send(msg).await
+                }
+            }
+        }).expect_err("").to_string();
+        assert_eq!(error, "#[async_handler] `type Result` is an AtomicResponse, which conflicts with `non_atomic`");
+    }
+
+    #[test]
+    fn test_rejects_destructured_ctx() {
+        let error = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
+            impl Handler<Ping> for AnActor {
+                type Result = ();
+                async fn handle(&mut self, msg: Ping, ctx @ _: &mut Self::Context) -> Self::Result {}
+            }
+        }).expect_err("").to_string();
+        assert_eq!(error, "#[async_handler] the context parameter must be a plain binding or `_`");
+    }
+
+    fn renamed(block: Block) -> String {
+        let renamed = RenameParams::new(Some("ctx".to_string())).fold_block(block);
+        let tokens = renamed.stmts.iter().map(|stmt| stmt.to_token_stream().to_string()).collect::<Vec<_>>();
+        tokens.join("\n")
+    }
+
+    #[test]
+    fn test_renames_named_actor_in_scope_only() {
+        let mut rename = RenameParams::new(None);
+        rename.actor = "act".to_string();
+        let renamed = rename.fold_block(parse_quote!({
+            act.count += 1;
+            let f = |act: &Other| act.count;
+            let msg = Msg { act };
+            let act = Other::new();
+            act.count;
+        }));
+        let tokens = renamed.stmts.iter().map(|stmt| stmt.to_token_stream().to_string()).collect::<Vec<_>>();
+        assert_eq!(tokens.join("\n"), [
+            "__self . count += 1 ;",
+            "let f = | act : & Other | act . count ;",
+            "let msg = Msg { act : __self } ;",
+            "let act = Other :: new () ;",
+            "act . count ;",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn test_renames_params() {
+        assert_eq!(renamed(parse_quote!({
+            self.count += 1;
+            ctx.notify(Msg { ctx });
+            println!("{:?}", self.ctx);
+        })), [
+            "__self . count += 1 ;",
+            "__ctx . notify (Msg { ctx : __ctx }) ;",
+            "println ! (\"{:?}\" , __self . ctx) ;",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn test_renames_params_in_macro_groups() {
+        assert_eq!(renamed(parse_quote!({
+            assert!(matches!(self.state, S::ctx));
+            let all = vec![self.a, (ctx.b)];
+            println!("{self:?} {{self}} {ctx:p} {0} {self.a} {}", self.a);
+            let local = 5;
+            format!("{self:?} {local} {self} {}", r.unwrap(),)
+        })), [
+            "assert ! (matches ! (__self . state , S :: ctx)) ;",
+            "let all = vec ! [__self . a , (__ctx . b)] ;",
+            "println ! (\"{__self:?} {{self}} {__ctx:p} {0} {self.a} {}\" , __self . a , __self = __self , __ctx = __ctx) ;",
+            "let local = 5 ;",
+            "format ! (\"{__self:?} {local} {__self} {}\" , r . unwrap () , __self = __self)",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn test_renames_params_after_ranges_in_macros() {
+        assert_eq!(renamed(parse_quote!({
+            let ranges = vec![..self.x, 0..=ctx.y, a.b..self.x];
+        })), [
+            "let ranges = vec ! [.. __self . x , 0 ..= __ctx . y , a . b .. __self . x] ;",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn test_renames_format_strings_only() {
+        assert_eq!(renamed(parse_quote!({
+            println!("{}", "{self}");
+            log::info!(target: "{ctx}", "{ctx:p} {}", "{self}");
+            assert_eq!(self.a, 1, "{self:?}");
+            let all = vec![format!("{self:?}"), "{self}".to_string()];
+            html!("{self}");
+        })), [
+            "println ! (\"{}\" , \"{self}\") ;",
+            "log :: info ! (target : \"{ctx}\" , \"{__ctx:p} {}\" , \"{self}\" , __ctx = __ctx) ;",
+            "assert_eq ! (__self . a , 1 , \"{__self:?}\" , __self = __self) ;",
+            "let all = vec ! [format ! (\"{__self:?}\" , __self = __self) , \"{self}\" . to_string ()] ;",
+            "html ! (\"{self}\") ;",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn test_renames_params_in_scope_only() {
+        assert_eq!(renamed(parse_quote!({
+            let kind = Kind::ctx(self.ctx);
+            impl Other { fn other(&self, ctx: u8) -> u8 { self.x + ctx } }
+            let f = |ctx: u8| ctx + 1;
+            match kind { Kind::ctx(ctx) => ctx, _ => ctx.id() };
+            if let Some(ctx) = kind.get() { ctx } else { ctx.id() };
+            for ctx in 0..2 { ctx; }
+            let ctx = ctx.address();
+            ctx;
+        })), [
+            "let kind = Kind :: ctx (__self . ctx) ;",
+            "impl Other { fn other (& self , ctx : u8) -> u8 { self . x + ctx } }",
+            "let f = | ctx : u8 | ctx + 1 ;",
+            "match kind { Kind :: ctx (ctx) => ctx , _ => __ctx . id () } ;",
+            "if let Some (ctx) = kind . get () { ctx } else { __ctx . id () } ;",
+            "for ctx in 0 .. 2 { ctx ; }",
+            "let ctx = __ctx . address () ;",
+            "ctx ;",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn test_keeps_stub_impl_on_errors() {
+        let result = async_handler_impl(quote!(), quote! {
+            impl Handler<Hit> for Cache {
+                type Result = u64;
+                async fn handle(&mut self, msg: Hit, ctx: &mut Self::Context) -> Self::Result {
+                    let entry = &mut self.cache[msg.0];
+                    self.db.send(msg).await;
+                    entry.hits
+                }
+            }
+        });
+
+        let expected = quote! {
+            impl Handler<Hit> for Cache {
+                type Result = actix::AtomicResponse<Self, u64>;
+                fn handle(&mut self, msg: Hit, ctx: &mut Self::Context) -> Self::Result {
+                    #[allow(unreachable_code, unused_variables)]
+                    {
+                        let _ = async move {
+                            let entry = &mut self.cache[msg.0];
+                            self.db.send(msg).await;
+                            entry.hits
+                        };
+                        loop {}
+                    }
+                }
+            }
+        };
+
+        let result = result.to_string();
+        assert!(result.starts_with(":: core :: compile_error !"), "{}", result);
+        assert!(result.ends_with(&expected.to_string()), "{}", result);
+    }
+
+    #[test]
+    fn test_inline_handler_calls() {
+        let call = |tokens| inline_handler_call(&parse_quote!(#tokens)).map(|call| call.to_string());
+        assert_eq!(call(quote!(__self.handle_inline(Ping, __ctx))).expect(""), "__self . __handle_ping (Ping , __ctx)");
+        assert_eq!(call(quote!(__self.handle_inline(IncrBy { by: 1 }, __ctx))).expect(""), "__self . __handle_incr_by (IncrBy { by : 1 } , __ctx)");
+        assert_eq!(call(quote!(__self.handle_inline::<api::Ping>(msg, __ctx))).expect(""), "__self . __handle_ping (msg , __ctx)");
+        assert!(call(quote!(__self.handle_inline(msg, __ctx))).is_err());
+    }
+
+    #[test]
+    fn test_leaves_nested_actor_await() {
+        let block: Block = parse_quote!({
+            let a = actor_await!(self.refresh());
+            println!("{}", a + actor_await!(self.refresh()));
+        });
+        let parts = split_awaits(&block, &HandlerArgs::default(), &Translation::default());
+        assert_eq!(parts.len(), 2);
+        assert!(parts[1].to_string().contains("actor_await ! (self . refresh ())"));
+    }
+
+    #[test]
+    fn test_debug_dump_marks_segments() {
+        let dump = debug_dump(&HandlerArgs::default(), &Translation::default(), quote! {
+            impl Handler<Ping> for Pinger {
+                type Result = u64;
+                async fn handle(&mut self, msg: Ping, ctx: &mut Self::Context) -> Self::Result {
+                    let result = self.ponger.send(msg).await;
+                    println!("pong {}", "\\o/");
+                    self.ponger.send(Ping(result.unwrap())).await;
+                    println!("pong {}", "again");
+                    result.unwrap()
+                }
+            }
+        }).expect("");
+
+        let lines = dump.lines().map(str::trim).collect::<Vec<_>>();
+        let marker = lines.iter().position(|l| *l == "// after: let result = self.ponger.send(msg).await;");
+        assert_eq!(marker.map(|m| lines[m + 1]), Some("let result = __res;"), "{}", dump);
+        let marker = lines.iter().position(|l| *l == "// after: self.ponger.send(Ping(result.unwrap())).await;");
+        assert_eq!(marker.map(|m| lines[m + 1]), Some("println!(\"pong {}\", \"again\");"), "{}", dump);
+        assert!(!dump.contains(SEGMENT_MARKER), "{}", dump);
+    }
+
     #[test]
     fn test_splits_awaits() {
         let block = parse_quote!({
@@ -443,7 +1760,112 @@ mod tests {
             println!("Final 2");
         });
 
-        let split = split_awaits(&block);
+        let split = split_awaits(&block, &HandlerArgs::default(), &Translation::default());
         assert_eq!(split.len(), 4);
     }
+
+    #[test]
+    fn test_rejects_state_borrows_across_awaits() {
+        let result = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
+            impl Handler<Hit> for Cache {
+                type Result = ();
+                async fn handle(&mut self, msg: Hit, ctx: &mut Self::Context) -> Self::Result {
+                    let entry = &mut self.cache[msg.0];
+                    self.db.send(msg).await;
+                    entry.hits += 1;
+                }
+            }
+        });
+
+        let message = result.expect_err("borrow across await").to_string();
+        assert!(message.contains("`entry` borrows the actor state across an await"), "{}", message);
+    }
+
+    #[test]
+    fn test_rejects_state_borrows_across_nested_awaits() {
+        assert!(async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
+            impl Handler<Hit> for Cache {
+                type Result = ();
+                async fn handle(&mut self, msg: Hit, ctx: &mut Self::Context) -> Self::Result {
+                    let entry = &mut self.cache[msg.0];
+                    if msg.1 {
+                        self.db.send(msg).await;
+                        entry.hits += 1;
+                    }
+                }
+            }
+        }).is_err());
+    }
+
+    #[test]
+    fn test_rejects_borrowing_accessors_across_awaits() {
+        let message = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
+            impl Handler<Hit> for Cache {
+                type Result = ();
+                async fn handle(&mut self, msg: Hit, ctx: &mut Self::Context) -> Self::Result {
+                    let entry = self.cache.get_mut(&msg.0).unwrap();
+                    self.db.send(msg).await;
+                    entry.hits += 1;
+                }
+            }
+        }).expect_err("borrow across await").to_string();
+        assert!(message.contains("`entry` borrows the actor state across an await"), "{}", message);
+
+        assert!(async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
+            impl Handler<Hit> for Cache {
+                type Result = ();
+                async fn handle(&mut self, msg: Hit, ctx: &mut Self::Context) -> Self::Result {
+                    let name = self.name.clone();
+                    self.db.send(msg).await;
+                    println!("{}", name);
+                }
+            }
+        }).is_ok());
+    }
+
+    #[test]
+    fn test_allows_state_reborrows_after_awaits() {
+        assert!(async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
+            impl Handler<Hit> for Cache {
+                type Result = ();
+                async fn handle(&mut self, msg: Hit, ctx: &mut Self::Context) -> Self::Result {
+                    let entry = &mut self.cache[msg.0];
+                    entry.misses += 1;
+                    self.db.send(msg).await;
+                    let entry = &mut self.cache[msg.0];
+                    entry.hits += 1;
+                }
+            }
+        }).is_ok());
+    }
+
+    #[test]
+    fn test_allows_rebound_names_of_borrows_after_awaits() {
+        assert!(async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
+            impl Handler<Hit> for Cache {
+                type Result = u64;
+                async fn handle(&mut self, msg: Hit, ctx: &mut Self::Context) -> Self::Result {
+                    let entry = self.cache.get(&msg.0);
+                    let hit = entry.is_some();
+                    self.db.send(msg).await;
+                    for entry in self.cache.keys() { println!("{}", entry); }
+                    if let Some(entry) = self.cache.get(&0) { println!("{}", entry); }
+                    let kind = Kind::entry;
+                    self.cache.values().map(|entry| *entry).sum()
+                }
+            }
+        }).is_ok());
+
+        let message = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
+            impl Handler<Hit> for Cache {
+                type Result = ();
+                async fn handle(&mut self, msg: Hit, ctx: &mut Self::Context) -> Self::Result {
+                    let entry = self.cache.get(&msg.0);
+                    self.db.send(msg).await;
+                    self.cache.values().for_each(|value| println!("{value} {entry:?}"));
+                }
+            }
+        }).expect_err("borrow across await").to_string();
+        assert!(message.contains("`entry` borrows the actor state across an await"), "{}", message);
+    }
 }
\ No newline at end of file
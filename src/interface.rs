@@ -0,0 +1,295 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{Error, FnArg, GenericArgument, Ident, ImplItem, Item, ItemImpl, ItemTrait, Pat, PathArguments, Result, ReturnType, Signature, TraitItem, Type, TypeReference};
+use syn::spanned::Spanned;
+
+use crate::args::{expand_with_args, HandlerArgs};
+use crate::r#impl::{ctx_future, internal_ident, output_type, translated_block, Translation};
+
+pub fn actor_interface_impl(attribute: TokenStream, input: TokenStream) -> TokenStream {
+    expand_with_args(attribute, |args| actor_interface_inner(args, input)
+        .unwrap_or_else(|e| e.to_compile_error()))
+}
+
+pub fn actor_interface_inner(args: &HandlerArgs, input: TokenStream) -> Result<TokenStream> {
+    match syn::parse2::<Item>(input)? {
+        Item::Trait(item_trait) => interface_trait(args, item_trait),
+        Item::Impl(item_impl) => interface_impl(args, item_impl),
+        item => Err(Error::new(item.span(), "#[actor_interface] can only be applied to a trait or its impl for an actor"))
+    }
+}
+
+// Each method becomes a message named after it, `incr_by` sending `IncrBy`. The trait is kept for the actor to
+// implement, each method returning the actor future that handles its message, and a client trait named after
+// it, `CounterClient`, sends them from the `Addr` of any actor handling all of them.
+fn interface_trait(args: &HandlerArgs, item_trait: ItemTrait) -> Result<TokenStream> {
+    if !item_trait.generics.params.is_empty() {
+        return Err(Error::new(item_trait.generics.span(), "#[actor_interface] traits can't be generic"))
+    }
+
+    let krate = &args.krate;
+    let vis = &item_trait.vis;
+    let mut messages = vec![];
+    let mut actor_fns = vec![];
+    let mut client_fns = vec![];
+    let mut client_impls = vec![];
+    let mut bounds = vec![];
+
+    for item in &item_trait.items {
+        let TraitItem::Fn(method) = item else {
+            return Err(Error::new(item.span(), "#[actor_interface] traits can only have methods, one per message"))
+        };
+        let Some(FnArg::Receiver(receiver)) = method.sig.inputs.first() else {
+            return Err(Error::new(method.sig.span(), "#[actor_interface] methods take `self`, as they're handled by the actor"))
+        };
+
+        let message = message_ident(&method.sig.ident);
+        let name = &method.sig.ident;
+        let (fields, types) = message_fields(&method.sig)?.into_iter().unzip::<_, _, Vec<_>, Vec<_>>();
+        let result = output_type(&method.sig.output);
+        let docs = method.attrs.iter().filter(|attr| attr.path().is_ident("doc")).collect::<Vec<_>>();
+
+        messages.push(quote! {
+            #(#docs)*
+            #vis struct #message {
+                #(#vis #fields: #types),*
+            }
+
+            impl #krate::Message for #message {
+                type Result = #result;
+            }
+        });
+
+        actor_fns.push(quote! {
+            #(#docs)*
+            fn #name(#receiver, #(#fields: #types,)* ctx: &mut <Self as #krate::Actor>::Context)
+                -> #krate::ResponseActFuture<Self, #result>;
+        });
+
+        // a `Result` carries mailbox errors in its own error type, anything else gets wrapped in one
+        let (output, response) = match result_types(&method.sig.output) {
+            Some((ok, err)) => (
+                quote!(std::result::Result<#ok, #err>),
+                quote! {
+                    async move {
+                        match request.await {
+                            Ok(result) => result,
+                            Err(error) => Err(error.into())
+                        }
+                    }
+                }
+            ),
+            None => (quote!(std::result::Result<#result, #krate::MailboxError>), quote!(request))
+        };
+
+        let signature = quote! {
+            fn #name(&self, #(#fields: #types),*) -> impl std::future::Future<Output = #output>
+        };
+        client_fns.push(quote! {
+            #(#docs)*
+            #signature;
+        });
+        client_impls.push(quote! {
+            #signature {
+                let request = self.send(#message { #(#fields),* });
+                #response
+            }
+        });
+        bounds.push(quote! {
+            A: #krate::Handler<#message>,
+            A::Context: #krate::dev::ToEnvelope<A, #message>
+        });
+    }
+
+    let attrs = &item_trait.attrs;
+    let ident = &item_trait.ident;
+    let supertraits = &item_trait.supertraits;
+    let plus = (!supertraits.is_empty()).then(|| quote!(+));
+    let client = format_ident!("{}Client", ident);
+    let client_doc = format!("Sends the [`{}`] messages to the address of an actor handling them", ident);
+
+    Ok(quote! {
+        #(#messages)*
+
+        #(#attrs)*
+        #vis trait #ident: #krate::Actor #plus #supertraits {
+            #(#actor_fns)*
+        }
+
+        #[doc = #client_doc]
+        #vis trait #client {
+            #(#client_fns)*
+        }
+
+        impl<A: #krate::Actor> #client for #krate::Addr<A> where #(#bounds),* {
+            #(#client_impls)*
+        }
+    })
+}
+
+// `impl Interface for Actor` implements the trait with each body translated into the actor future it returns,
+// and the Handler impls for the interface messages calling them. The methods take the message fields as
+// parameters, optionally followed by the context.
+fn interface_impl(args: &HandlerArgs, mut item_impl: ItemImpl) -> Result<TokenStream> {
+    let Some((_, interface, _)) = item_impl.trait_.clone() else {
+        return Err(Error::new(item_impl.span(), "#[actor_interface] impls implement the interface for an actor, as in `impl Interface for Actor`"))
+    };
+
+    let krate = &args.krate;
+    let mut handlers = vec![];
+
+    for item in &mut item_impl.items {
+        let ImplItem::Fn(method) = item else {
+            return Err(Error::new(item.span(), "#[actor_interface] impls can only have the interface methods"))
+        };
+
+        let (args, attrs) = args.for_method(&method.attrs)?;
+        let attrs = attrs.into_iter().cloned().collect::<Vec<_>>();
+        method.attrs.clone_from(&attrs);
+
+        // messages are `'static`, so a trailing `&mut` parameter can only be the context
+        let has_ctx = matches!(method.sig.inputs.last(),
+            Some(FnArg::Typed(ctx)) if matches!(&*ctx.ty, Type::Reference(TypeReference { mutability: Some(_), .. })));
+        if !has_ctx {
+            method.sig.inputs.push(parse_quote!(_: &mut Self::Context));
+        }
+
+        let mut fields = vec![];
+        let mut inputs = method.sig.inputs.iter_mut().skip(1).collect::<Vec<_>>();
+        let Some(FnArg::Typed(ctx)) = inputs.pop() else {
+            unreachable!("the context was pushed if missing")
+        };
+        for param in inputs {
+            let FnArg::Typed(param) = param else {
+                return Err(Error::new(param.span(), "#[actor_interface] methods take `&mut self` first"))
+            };
+            let Pat::Ident(pat) = &*param.pat else {
+                return Err(Error::new(param.pat.span(), "#[actor_interface] parameters are message fields, so they need a name"))
+            };
+            fields.push(pat.ident.clone());
+        }
+
+        let future = ctx_future(&args, &Translation::default(), &method.block, &mut ctx.pat, "#[actor_interface]", false)?;
+        // has to match the trait, where `Self::Context` would be ambiguous
+        ctx.ty = parse_quote!(&mut <Self as #krate::Actor>::Context);
+
+        let result = output_type(&method.sig.output);
+        method.sig.asyncness = None;
+        method.sig.output = parse_quote!(-> #krate::ResponseActFuture<Self, #result>);
+        method.block = translated_block(quote!({
+            use #krate::ActorFutureExt;
+            Box::pin(#future)
+        }), method.span())?;
+
+        let mut message = interface.clone();
+        if let Some(last) = message.segments.last_mut() {
+            last.ident = message_ident(&method.sig.ident);
+            last.arguments = PathArguments::None;
+        }
+
+        let name = &method.sig.ident;
+        let (msg, ctx) = (internal_ident("__msg"), internal_ident("__ctx"));
+        let call = quote!(<Self as #interface>::#name(self, #(#msg.#fields,)* #ctx));
+        let (result_ty, response) = if args.is_atomic {
+            (quote!(#krate::AtomicResponse<Self, #result>), quote!(#krate::AtomicResponse::new(#call)))
+        } else {
+            (quote!(#krate::ResponseActFuture<Self, #result>), call)
+        };
+
+        let self_ty = &item_impl.self_ty;
+        let (impl_generics, _, where_clause) = item_impl.generics.split_for_impl();
+        handlers.push(quote! {
+            #(#attrs)*
+            impl #impl_generics #krate::Handler<#message> for #self_ty #where_clause {
+                type Result = #result_ty;
+
+                fn handle(&mut self, #msg: #message, #ctx: &mut Self::Context) -> Self::Result {
+                    #response
+                }
+            }
+        });
+    }
+
+    Ok(quote! {
+        #item_impl
+
+        #(#handlers)*
+    })
+}
+
+fn message_ident(method: &Ident) -> Ident {
+    let name = method.to_string().split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars.next().map(|first| first.to_uppercase().chain(chars).collect::<String>()).unwrap_or_default()
+        })
+        .collect::<String>();
+    Ident::new(&name, method.span())
+}
+
+fn message_fields(sig: &Signature) -> Result<Vec<(&Ident, &Type)>> {
+    sig.inputs.iter().skip(1).map(|param| match param {
+        FnArg::Typed(typed) => match &*typed.pat {
+            Pat::Ident(pat) => Ok((&pat.ident, &*typed.ty)),
+            pat => Err(Error::new(pat.span(), "#[actor_interface] parameters are message fields, so they need a name"))
+        },
+        FnArg::Receiver(receiver) => Err(Error::new(receiver.span(), "#[actor_interface] methods take `self` first"))
+    }).collect()
+}
+
+// `Some((T, E))` for a `Result<T, E>`
+fn result_types(output: &ReturnType) -> Option<(&Type, &Type)> {
+    let ReturnType::Type(_, ty) = output else {
+        return None
+    };
+    let Type::Path(path) = &**ty else {
+        return None
+    };
+    let last = path.path.segments.last()?;
+    match &last.arguments {
+        PathArguments::AngleBracketed(generics) if last.ident == "Result" && generics.args.len() == 2 => {
+            match (&generics.args[0], &generics.args[1]) {
+                (GenericArgument::Type(ok), GenericArgument::Type(err)) => Some((ok, err)),
+                _ => None
+            }
+        }
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::quote;
+
+    use super::*;
+
+    #[test]
+    fn test_names_messages_after_methods() {
+        assert_eq!(message_ident(&parse_quote!(incr)), "Incr");
+        assert_eq!(message_ident(&parse_quote!(incr_by_two)), "IncrByTwo");
+    }
+
+    #[test]
+    fn test_flattens_result_types() {
+        assert!(result_types(&parse_quote!(-> Result<u64, Error>)).is_some());
+        assert!(result_types(&parse_quote!(-> std::io::Result<u64>)).is_none());
+        assert!(result_types(&parse_quote!(-> u64)).is_none());
+    }
+
+    #[test]
+    fn test_rejects_unnamed_parameters() {
+        let error = actor_interface_inner(&HandlerArgs::default(), quote! {
+            trait Counter {
+                async fn incr(&mut self, (a, b): (u64, u64)) -> u64;
+            }
+        }).expect_err("").to_string();
+        assert_eq!(error, "#[actor_interface] parameters are message fields, so they need a name");
+    }
+
+    #[test]
+    fn test_requires_interface_impl() {
+        assert!(actor_interface_inner(&HandlerArgs::default(), quote! {
+            impl Counter {}
+        }).is_err());
+    }
+}
@@ -4,9 +4,20 @@ extern crate syn;
 
 use proc_macro::TokenStream;
 
-use crate::r#impl::async_handler_impl;
+use crate::actor::async_actor_impl;
+use crate::actor_async::actor_async_impl;
+use crate::actor_fn::actor_fn_impl;
+use crate::handlers::async_handlers_impl;
+use crate::interface::actor_interface_impl;
+use crate::r#impl::{actor_await_impl, async_handler_impl};
 
+mod actor;
+mod actor_async;
+mod actor_fn;
+mod args;
+mod handlers;
 mod r#impl;
+mod interface;
 #[cfg(test)]
 mod translation_test;
 
@@ -17,4 +28,55 @@ pub fn async_handler(attribute: TokenStream, input: TokenStream) -> TokenStream
     let input = parse_macro_input!(input);
     async_handler_impl(attribute, input).into()
 
-}
\ No newline at end of file
+}
+
+#[proc_macro_attribute]
+pub fn async_handlers(attribute: TokenStream, input: TokenStream) -> TokenStream {
+
+    let attribute = parse_macro_input!(attribute);
+    let input = parse_macro_input!(input);
+    async_handlers_impl(attribute, input).into()
+
+}
+
+#[proc_macro_attribute]
+pub fn actor_interface(attribute: TokenStream, input: TokenStream) -> TokenStream {
+
+    let attribute = parse_macro_input!(attribute);
+    let input = parse_macro_input!(input);
+    actor_interface_impl(attribute, input).into()
+
+}
+
+#[proc_macro_attribute]
+pub fn actor_fn(attribute: TokenStream, input: TokenStream) -> TokenStream {
+
+    let attribute = parse_macro_input!(attribute);
+    let input = parse_macro_input!(input);
+    actor_fn_impl(attribute, input).into()
+
+}
+
+#[proc_macro]
+pub fn actor_await(_input: TokenStream) -> TokenStream {
+
+    actor_await_impl().into()
+
+}
+
+#[proc_macro_attribute]
+pub fn async_actor(attribute: TokenStream, input: TokenStream) -> TokenStream {
+
+    let attribute = parse_macro_input!(attribute);
+    let input = parse_macro_input!(input);
+    async_actor_impl(attribute, input).into()
+
+}
+
+#[proc_macro]
+pub fn actor_async(input: TokenStream) -> TokenStream {
+
+    let input = parse_macro_input!(input);
+    actor_async_impl(input).into()
+
+}
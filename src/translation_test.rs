@@ -1,12 +1,18 @@
-use crate::r#impl::async_handler_inner;
+use crate::actor::async_actor_inner;
+use crate::actor_async::actor_async_inner;
+use crate::actor_fn::actor_fn_inner;
+use crate::args::HandlerArgs;
+use crate::handlers::async_handlers_inner;
+use crate::interface::actor_interface_inner;
+use crate::r#impl::{async_handler_inner, Translation};
 use quote::quote;
 use rust_format::Formatter;
-use syn::ExprIf;
 
 #[test]
 fn test_splits_awaits_integration() {
-    let result = async_handler_inner(true, quote! {
+    let result = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
         impl Handler<T> for AnActor {
+            type Result = ();
             async fn handle(&mut self, msg: Ping, ctx: &mut Self::Context) -> Self::Result {
                 println!("Before 1");
                 println!("Before 2");
@@ -22,6 +28,7 @@ fn test_splits_awaits_integration() {
 
     let expected =
 r#"impl Handler<T> for AnActor {
+    type Result = actix::AtomicResponse<Self, ()>;
     fn handle(&mut self, msg: Ping, ctx: &mut Self::Context) -> Self::Result {
         use actix::ActorFutureExt;
         actix::AtomicResponse::new(Box::pin(
@@ -61,7 +68,7 @@ r#"impl Handler<T> for AnActor {
 #[test]
 fn test_splits_awaits_no_awaits_integration() {
 
-    let result = async_handler_inner(true, quote! {
+    let result = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
         impl Handler<T> for AnActor {
 
             type Result = String;
@@ -96,7 +103,7 @@ r#"impl Handler<T> for AnActor {
 
 #[test]
 fn test_await_return_value_assignment() {
-    let result = async_handler_inner(true, quote! {
+    let result = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
         impl Handler<GetVariables> for ResultAssignment {
             type Result = u64;
             async fn handle(&mut self, msg: GetVariables, ctx: &mut Self::Context) -> Self::Result {
@@ -122,6 +129,7 @@ fn test_await_return_value_assignment() {
                             let result1 = __res;
                             actix::fut::wrap_future::<_, Self>(__self.other_actor.send(result1))
                                 .map(move |__res, __self, __ctx| {
+                                    let _ = &result2;
                                     result2 = __res;
                                     result1 + result2
                                 })
@@ -140,7 +148,7 @@ fn test_await_return_value_assignment() {
 
 #[test]
 fn test_if_single_branch() {
-    let result = async_handler_inner(true, quote! {
+    let result = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
         impl Handler<Conditional> for ResultAssignment {
             type Result = u64;
             async fn handle(&mut self, msg: Conditional, ctx: &mut Self::Context) -> Self::Result {
@@ -164,11 +172,8 @@ fn test_if_single_branch() {
                 move |__res, __self, __ctx| {
                     if msg.0 > 0 {
                         Box::pin({
-                            actix::fut::wrap_future::<_, Self>(__self.other_actor.send(0)).map(
-                                move |__res, __self, __ctx| {
-                                    ()
-                                },
-                            )
+                            actix::fut::wrap_future::<_, Self>(__self.other_actor.send(0))
+                                .map(move |__res, __self, __ctx| {})
                         })
                             as std::pin::Pin<
                                 Box<dyn actix::fut::future::ActorFuture<Self, Output = _>>,
@@ -192,7 +197,7 @@ fn test_if_single_branch() {
 
 #[test]
 fn test_if_branch_awaits() {
-    let result = async_handler_inner(true, quote! {
+    let result = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
         impl Handler<Conditional> for ResultAssignment {
             type Result = u64;
             async fn handle(&mut self, msg: Conditional, ctx: &mut Self::Context) -> Self::Result {
@@ -225,11 +230,7 @@ fn test_if_branch_awaits() {
                                     actix::fut::wrap_future::<_, Self>(
                                         __self.other_actor.send(part),
                                     )
-                                    .map(
-                                        move |__res, __self, __ctx| {
-                                            ()
-                                        },
-                                    )
+                                    .map(move |__res, __self, __ctx| {})
                                 },
                             )
                         })
@@ -260,7 +261,7 @@ fn test_if_branch_awaits() {
 
 #[test]
 fn test_if_branch_awaits_return_value() {
-    let result = async_handler_inner(true, quote! {
+    let result = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
         impl Handler<Conditional> for ResultAssignment {
             type Result = u64;
             async fn handle(&mut self, msg: Conditional, ctx: &mut Self::Context) -> Self::Result {
@@ -322,7 +323,7 @@ fn test_if_branch_awaits_return_value() {
 
 #[test]
 fn test_if_both_branches_await() {
-    let result = async_handler_inner(true, quote! {
+    let result = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
         impl Handler<Conditional> for ResultAssignment {
             type Result = u64;
             async fn handle(&mut self, msg: Conditional, ctx: &mut Self::Context) -> Self::Result {
@@ -345,22 +346,16 @@ fn test_if_both_branches_await() {
                 move |__res, __self, __ctx| {
                     if msg.0 > 0 {
                         Box::pin({
-                            actix::fut::wrap_future::<_, Self>(__self.other_actor.send(0)).map(
-                                move |__res, __self, __ctx| {
-                                    ()
-                                },
-                            )
+                            actix::fut::wrap_future::<_, Self>(__self.other_actor.send(0))
+                                .map(move |__res, __self, __ctx| {})
                         })
                             as std::pin::Pin<
                                 Box<dyn actix::fut::future::ActorFuture<Self, Output = _>>,
                             >
                     } else {
                         Box::pin({
-                            actix::fut::wrap_future::<_, Self>(__self.negative_actor.send(42)).map(
-                                move |__res, __self, __ctx| {
-                                    ()
-                                },
-                            )
+                            actix::fut::wrap_future::<_, Self>(__self.negative_actor.send(42))
+                                .map(move |__res, __self, __ctx| {})
                         })
                     }
                 },
@@ -376,7 +371,7 @@ fn test_if_both_branches_await() {
 
 #[test]
 fn test_if_else_awaits() {
-    let result = async_handler_inner(true, quote! {
+    let result = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
         impl Handler<Conditional> for ResultAssignment {
             type Result = u64;
             async fn handle(&mut self, msg: Conditional, ctx: &mut Self::Context) -> Self::Result {
@@ -423,7 +418,7 @@ fn test_if_else_awaits() {
 
 #[test]
 fn test_if_else_chain_awaits() {
-    let result = async_handler_inner(true, quote! {
+    let result = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
         impl Handler<Conditional> for ResultAssignment {
             type Result = u64;
             async fn handle(&mut self, msg: Conditional, ctx: &mut Self::Context) -> Self::Result {
@@ -478,7 +473,7 @@ fn test_if_else_chain_awaits() {
 
 #[test]
 fn test_if_assigns() {
-    let result = async_handler_inner(true, quote! {
+    let result = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
         impl Handler<Conditional> for ResultAssignment {
             type Result = u64;
             async fn handle(&mut self, msg: Conditional, ctx: &mut Self::Context) -> Self::Result {
@@ -514,6 +509,7 @@ fn test_if_assigns() {
                         Box::pin(actix::fut::ready({ 15 }))
                     }
                     .then(move |__res, __self, __ctx| {
+                        let _ = &result;
                         result = __res;
                         actix::fut::wrap_future::<_, Self>(__self.other_actor.send(result))
                     })
@@ -530,7 +526,7 @@ fn test_if_assigns() {
 
 #[test]
 fn test_for_loop() {
-    let result = async_handler_inner(true, quote! {
+    let result = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
         impl Handler<Conditional> for ResultAssignment {
             type Result = u64;
             async fn handle(&mut self, msg: Conditional, ctx: &mut Self::Context) -> Self::Result {
@@ -557,9 +553,23 @@ fn test_for_loop() {
             actix::fut::wrap_future::<_, Self>(actix::fut::ready(())).then(
                 move |__res, __self, __ctx| {
                     use actix::ActorStreamExt;
-                    actix::fut::wrap_stream(futures::stream::iter(IntoIterator::into_iter(
-                        __self.pongers.clone(),
-                    )))
+                    actix::fut::wrap_stream({
+                        let __iter = IntoIterator::into_iter(__self.pongers.clone());
+                        {
+                            struct __IterStream<I>(Box<I>);
+                            impl<I: Iterator> actix::prelude::Stream for __IterStream<I> {
+                                type Item = I::Item;
+                                fn poll_next(
+                                    self: std::pin::Pin<&mut Self>,
+                                    _: &mut std::task::Context<'_>,
+                                ) -> std::task::Poll<Option<I::Item>>
+                                {
+                                    std::task::Poll::Ready(self.get_mut().0.next())
+                                }
+                            }
+                            __IterStream(Box::new(__iter))
+                        }
+                    })
                     .fold((), move |__acc, ponger, __self, __ctx| {
                         Box::pin({
                             println!("pre loop");
@@ -569,7 +579,6 @@ fn test_for_loop() {
                                     actix::fut::wrap_future::<_, Self>(ponger.send(Ping(msg.0 + 1)))
                                         .map(move |__res, __self, __ctx| {
                                             println!("end loop");
-                                            ()
                                         })
                                 },
                             )
@@ -594,7 +603,7 @@ fn test_for_loop() {
 
 #[test]
 fn test_for_loop_returns() {
-    let result = async_handler_inner(true, quote! {
+    let result = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
         impl Handler<Conditional> for ResultAssignment {
             type Result = u64;
             async fn handle(&mut self, msg: Conditional, ctx: &mut Self::Context) -> Self::Result {
@@ -623,10 +632,25 @@ fn test_for_loop_returns() {
                 move |__res, __self, __ctx| {
                     let mut i;
                     use actix::ActorStreamExt;
-                    actix::fut::wrap_stream(futures::stream::iter(IntoIterator::into_iter(
-                        __self.pongers.clone(),
-                    )))
+                    actix::fut::wrap_stream({
+                        let __iter = IntoIterator::into_iter(__self.pongers.clone());
+                        {
+                            struct __IterStream<I>(Box<I>);
+                            impl<I: Iterator> actix::prelude::Stream for __IterStream<I> {
+                                type Item = I::Item;
+                                fn poll_next(
+                                    self: std::pin::Pin<&mut Self>,
+                                    _: &mut std::task::Context<'_>,
+                                ) -> std::task::Poll<Option<I::Item>>
+                                {
+                                    std::task::Poll::Ready(self.get_mut().0.next())
+                                }
+                            }
+                            __IterStream(Box::new(__iter))
+                        }
+                    })
                     .fold(i, move |__acc, ponger, __self, __ctx| {
+                        #[allow(unused_mut)]
                         let mut i = __acc;
                         Box::pin({
                             println!("pre loop");
@@ -646,6 +670,7 @@ fn test_for_loop_returns() {
                             >
                     })
                     .then(move |__res, __self, __ctx| {
+                        let _ = &i;
                         i = __res;
                         actix::fut::wrap_future::<_, Self>(__self.pongers[0].send(msg))
                     })
@@ -658,4 +683,1024 @@ fn test_for_loop_returns() {
 
     let actual = rust_format::RustFmt::default().format_tokens(result.clone().expect("")).expect("");
     assert_eq!(expected, actual);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_crate_paths() {
+    let args = HandlerArgs {
+        is_atomic: false,
+        krate: parse_quote!(facade::actix),
+        ..Default::default()
+    };
+    let result = async_handler_inner(&args, &Translation::default(), quote! {
+        impl Handler<Ping> for Pinger {
+            type Result = u64;
+            async fn handle(&mut self, msg: Ping, ctx: &mut Self::Context) -> Self::Result {
+                for ponger in self.pongers.clone() {
+                    ponger.send(msg).await;
+                }
+                if msg.0 > 0 {
+                    self.pongers[0].send(msg).await;
+                }
+                42
+            }
+        }
+    });
+
+    let expected =
+        r#"impl Handler<Ping> for Pinger {
+    type Result = facade::actix::ResponseActFuture<Self, u64>;
+    fn handle(&mut self, msg: Ping, ctx: &mut Self::Context) -> Self::Result {
+        use facade::actix::ActorFutureExt;
+        Box::pin(
+            facade::actix::fut::wrap_future::<_, Self>(facade::actix::fut::ready(())).then(
+                move |__res, __self, __ctx| {
+                    use facade::actix::ActorStreamExt;
+                    facade::actix::fut::wrap_stream({
+                        let __iter = IntoIterator::into_iter(__self.pongers.clone());
+                        {
+                            struct __IterStream<I>(Box<I>);
+                            impl<I: Iterator> facade::actix::prelude::Stream for __IterStream<I> {
+                                type Item = I::Item;
+                                fn poll_next(
+                                    self: std::pin::Pin<&mut Self>,
+                                    _: &mut std::task::Context<'_>,
+                                ) -> std::task::Poll<Option<I::Item>>
+                                {
+                                    std::task::Poll::Ready(self.get_mut().0.next())
+                                }
+                            }
+                            __IterStream(Box::new(__iter))
+                        }
+                    })
+                    .fold((), move |__acc, ponger, __self, __ctx| {
+                        Box::pin({
+                            facade::actix::fut::wrap_future::<_, Self>(ponger.send(msg))
+                                .map(move |__res, __self, __ctx| {})
+                        })
+                            as std::pin::Pin<
+                                Box<dyn facade::actix::fut::future::ActorFuture<Self, Output = _>>,
+                            >
+                    })
+                    .then(move |__res, __self, __ctx| {
+                        if msg.0 > 0 {
+                            Box::pin({
+                                facade::actix::fut::wrap_future::<_, Self>(
+                                    __self.pongers[0].send(msg),
+                                )
+                                .map(move |__res, __self, __ctx| {})
+                            })
+                                as std::pin::Pin<
+                                    Box<
+                                        dyn facade::actix::fut::future::ActorFuture<
+                                            Self,
+                                            Output = _,
+                                        >,
+                                    >,
+                                >
+                        } else {
+                            Box::pin(facade::actix::fut::ready(()))
+                        }
+                        .map(move |__res, __self, __ctx| 42)
+                    })
+                },
+            ),
+        )
+    }
+}
+"#;
+
+    let actual = rust_format::RustFmt::default().format_tokens(result.clone().expect("")).expect("");
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_generic_impl() {
+    let result = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
+        impl<M: Numbered + 'static> actix::Handler<M> for Router {
+            type Result = u64;
+            async fn handle(&mut self, msg: M, ctx: &mut Self::Context) -> Self::Result {
+                let result = self.delegate.send(Count(msg.number())).await;
+                result.unwrap()
+            }
+        }
+    });
+
+    let expected =
+        r#"impl<M: Numbered + 'static> actix::Handler<M> for Router {
+    type Result = actix::AtomicResponse<Self, u64>;
+    fn handle(&mut self, msg: M, ctx: &mut Self::Context) -> Self::Result {
+        use actix::ActorFutureExt;
+        actix::AtomicResponse::new(Box::pin(
+            actix::fut::wrap_future::<_, Self>(actix::fut::ready(())).then(
+                move |__res, __self, __ctx| {
+                    actix::fut::wrap_future::<_, Self>(__self.delegate.send(Count(msg.number())))
+                        .map(move |__res, __self, __ctx| {
+                            let result = __res;
+                            result.unwrap()
+                        })
+                },
+            ),
+        ))
+    }
+}
+"#;
+
+    let actual = rust_format::RustFmt::default().format_tokens(result.clone().expect("")).expect("");
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_inferred_result_type() {
+    let result = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
+        impl Handler<Ping> for AnActor {
+            async fn handle(&mut self, msg: Ping, ctx: &mut Context<Self>) -> u64 {
+                let result = self.delegate.send(msg).await;
+                result.unwrap()
+            }
+        }
+    });
+
+    let expected =
+        r#"impl Handler<Ping> for AnActor {
+    type Result = actix::AtomicResponse<Self, u64>;
+    fn handle(&mut self, msg: Ping, ctx: &mut Context<Self>) -> Self::Result {
+        use actix::ActorFutureExt;
+        actix::AtomicResponse::new(Box::pin(
+            actix::fut::wrap_future::<_, Self>(actix::fut::ready(())).then(
+                move |__res, __self, __ctx| {
+                    actix::fut::wrap_future::<_, Self>(__self.delegate.send(msg)).map(
+                        move |__res, __self, __ctx| {
+                            let result = __res;
+                            result.unwrap()
+                        },
+                    )
+                },
+            ),
+        ))
+    }
+}
+"#;
+
+    let actual = rust_format::RustFmt::default().format_tokens(result.clone().expect("")).expect("");
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_parameter_patterns() {
+    let result = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
+        impl Handler<Count> for AnActor {
+            type Result = u64;
+            async fn handle(&mut self, Count(n): Count, _: &mut Self::Context) -> Self::Result {
+                let result = self.delegate.send(Count(n)).await;
+                result.unwrap() + n
+            }
+        }
+    });
+
+    let expected =
+        r#"impl Handler<Count> for AnActor {
+    type Result = actix::AtomicResponse<Self, u64>;
+    fn handle(&mut self, Count(n): Count, __ctx: &mut Self::Context) -> Self::Result {
+        use actix::ActorFutureExt;
+        actix::AtomicResponse::new(Box::pin(
+            actix::fut::wrap_future::<_, Self>(actix::fut::ready(())).then(
+                move |__res, __self, __ctx| {
+                    actix::fut::wrap_future::<_, Self>(__self.delegate.send(Count(n))).map(
+                        move |__res, __self, __ctx| {
+                            let result = __res;
+                            result.unwrap() + n
+                        },
+                    )
+                },
+            ),
+        ))
+    }
+}
+"#;
+
+    let actual = rust_format::RustFmt::default().format_tokens(result.clone().expect("")).expect("");
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_ctx_used_in_continuations() {
+    let result = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
+        impl Handler<Ping> for AnActor {
+            type Result = ();
+            async fn handle(&mut self, msg: Ping, ctx: &mut Context<Self>) -> Self::Result {
+                self.delegate.send(msg).await;
+                ctx.stop();
+            }
+        }
+    });
+
+    let expected =
+        r#"impl Handler<Ping> for AnActor {
+    type Result = actix::AtomicResponse<Self, ()>;
+    fn handle(&mut self, msg: Ping, __ctx: &mut Context<Self>) -> Self::Result {
+        use actix::ActorFutureExt;
+        actix::AtomicResponse::new(Box::pin(
+            actix::fut::wrap_future::<_, Self>(actix::fut::ready(())).then(
+                move |__res, __self, __ctx| {
+                    actix::fut::wrap_future::<_, Self>(__self.delegate.send(msg)).map(
+                        move |__res, __self, __ctx| {
+                            __ctx.stop();
+                        },
+                    )
+                },
+            ),
+        ))
+    }
+}
+"#;
+
+    let actual = rust_format::RustFmt::default().format_tokens(result.clone().expect("")).expect("");
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_statement_attributes() {
+    let result = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
+        impl Handler<Ping> for AnActor {
+            type Result = u64;
+            async fn handle(&mut self, msg: Ping, ctx: &mut Self::Context) -> Self::Result {
+                #[cfg(feature = "audit")]
+                self.audit.send(msg).await;
+                #[allow(unused_variables)]
+                let result = self.delegate.send(msg).await;
+                0
+            }
+        }
+    });
+
+    let expected =
+        r#"impl Handler<Ping> for AnActor {
+    type Result = actix::AtomicResponse<Self, u64>;
+    fn handle(&mut self, msg: Ping, ctx: &mut Self::Context) -> Self::Result {
+        use actix::ActorFutureExt;
+        actix::AtomicResponse::new(Box::pin(
+            actix::fut::wrap_future::<_, Self>(actix::fut::ready(())).then(
+                move |__res, __self, __ctx| {
+                    {
+                        #[cfg(feature = "audit")]
+                        let __fut = { actix::fut::wrap_future::<_, Self>(__self.audit.send(msg)) };
+                        #[cfg(not(feature = "audit"))]
+                        let __fut = actix::fut::wrap_future::<_, Self>(actix::fut::ready(()));
+                        __fut
+                    }
+                    .then(move |__res, __self, __ctx| {
+                        {
+                            #[allow(unused_variables)]
+                            let __fut =
+                                { actix::fut::wrap_future::<_, Self>(__self.delegate.send(msg)) };
+                            __fut
+                        }
+                        .map(move |__res, __self, __ctx| {
+                            #[allow(unused_variables)]
+                            let result = __res;
+                            0
+                        })
+                    })
+                },
+            ),
+        ))
+    }
+}
+"#;
+
+    let actual = rust_format::RustFmt::default().format_tokens(result.clone().expect("")).expect("");
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_sync_handler() {
+    let handler = quote! {
+        impl Handler<Ping> for AnActor {
+            type Result = u64;
+            fn handle(&mut self, msg: Ping, ctx: &mut Self::Context) -> Self::Result {
+                self.pings += 1;
+                self.pings
+            }
+        }
+    };
+    let result = async_handler_inner(&HandlerArgs::default(), &Translation::default(), handler.clone());
+    assert_eq!(handler.to_string(), result.expect("").to_string());
+
+    let result = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
+        impl Handler<Ping> for AnActor {
+            fn handle(&mut self, msg: Ping, ctx: &mut Self::Context) -> Pong {
+                if msg.0 == 0 {
+                    return Pong(0);
+                }
+                Pong(msg.0)
+            }
+        }
+    });
+
+    let expected =
+        r#"impl Handler<Ping> for AnActor {
+    type Result = actix::MessageResult<Ping>;
+    fn handle(&mut self, msg: Ping, ctx: &mut Self::Context) -> Self::Result {
+        #[allow(clippy::redundant_closure_call)]
+        let result = (|| -> Pong {
+            if msg.0 == 0 {
+                return Pong(0);
+            }
+            Pong(msg.0)
+        })();
+        actix::MessageResult(result)
+    }
+}
+"#;
+
+    let actual = rust_format::RustFmt::default().format_tokens(result.clone().expect("")).expect("");
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_declared_response_type() {
+    let result = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
+        impl Handler<Ping> for AnActor {
+            type Result = ResponseActFuture<Self, u64>;
+            async fn handle(&mut self, msg: Ping, ctx: &mut Self::Context) -> Self::Result {
+                let result = self.delegate.send(msg).await;
+                result.unwrap()
+            }
+        }
+    });
+
+    let expected =
+        r#"impl Handler<Ping> for AnActor {
+    type Result = ResponseActFuture<Self, u64>;
+    fn handle(&mut self, msg: Ping, ctx: &mut Self::Context) -> Self::Result {
+        use actix::ActorFutureExt;
+        Box::pin(
+            actix::fut::wrap_future::<_, Self>(actix::fut::ready(())).then(
+                move |__res, __self, __ctx| {
+                    actix::fut::wrap_future::<_, Self>(__self.delegate.send(msg)).map(
+                        move |__res, __self, __ctx| {
+                            let result = __res;
+                            result.unwrap()
+                        },
+                    )
+                },
+            ),
+        )
+    }
+}
+"#;
+
+    let actual = rust_format::RustFmt::default().format_tokens(result.clone().expect("")).expect("");
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_async_handlers() {
+    let result = async_handlers_inner(&HandlerArgs::default(), quote! {
+        impl AnActor {
+            async fn ping(&mut self, msg: Ping, ctx: &mut Context<Self>) -> u64 {
+                let result = self.delegate.send(msg).await;
+                result.unwrap()
+            }
+
+            /// forwards the pong
+            #[non_atomic]
+            async fn pong(&mut self, msg: Pong, _: &mut Context<Self>) {
+                self.delegate.send(msg).await;
+            }
+
+            fn helper(&self) -> u64 {
+                1
+            }
+        }
+    });
+
+    let expected =
+        r#"impl AnActor {
+    fn helper(&self) -> u64 {
+        1
+    }
+}
+impl actix::Handler<Ping> for AnActor {
+    type Result = actix::AtomicResponse<Self, u64>;
+    fn handle(&mut self, msg: Ping, ctx: &mut Context<Self>) -> Self::Result {
+        use actix::ActorFutureExt;
+        actix::AtomicResponse::new(Box::pin(
+            actix::fut::wrap_future::<_, Self>(actix::fut::ready(())).then(
+                move |__res, __self, __ctx| {
+                    actix::fut::wrap_future::<_, Self>(__self.delegate.send(msg)).map(
+                        move |__res, __self, __ctx| {
+                            let result = __res;
+                            result.unwrap()
+                        },
+                    )
+                },
+            ),
+        ))
+    }
+}
+#[doc = r" forwards the pong"]
+impl actix::Handler<Pong> for AnActor {
+    type Result = actix::ResponseActFuture<Self, ()>;
+    fn handle(&mut self, msg: Pong, __ctx: &mut Context<Self>) -> Self::Result {
+        use actix::ActorFutureExt;
+        Box::pin(
+            actix::fut::wrap_future::<_, Self>(actix::fut::ready(()))
+                .then(move |__res, __self, __ctx| {
+                    actix::fut::wrap_future::<_, Self>(__self.delegate.send(msg))
+                })
+                .map(|_, _, _| {}),
+        )
+    }
+}
+"#;
+
+    let actual = rust_format::RustFmt::default().format_tokens(result.clone().expect("")).expect("");
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_async_handlers_module() {
+    let result = async_handlers_inner(&HandlerArgs { is_atomic: false, ..Default::default() }, quote! {
+        mod handlers {
+            use super::*;
+
+            impl Handler<Ping> for AnActor {
+                type Result = u64;
+                async fn handle(&mut self, msg: Ping, ctx: &mut Self::Context) -> Self::Result {
+                    let result = self.delegate.send(msg).await;
+                    result.unwrap()
+                }
+            }
+
+            mod nested {
+                impl actix::Handler<Pong> for AnActor {
+                    type Result = u64;
+                    async fn handle(&mut self, msg: Pong, ctx: &mut Self::Context) -> Self::Result {
+                        let result = self.delegate.send(msg).await;
+                        result.unwrap()
+                    }
+                }
+            }
+        }
+    });
+
+    let expected =
+        r#"mod handlers {
+    use super::*;
+    impl Handler<Ping> for AnActor {
+        type Result = actix::ResponseActFuture<Self, u64>;
+        fn handle(&mut self, msg: Ping, ctx: &mut Self::Context) -> Self::Result {
+            use actix::ActorFutureExt;
+            Box::pin(
+                actix::fut::wrap_future::<_, Self>(actix::fut::ready(())).then(
+                    move |__res, __self, __ctx| {
+                        actix::fut::wrap_future::<_, Self>(__self.delegate.send(msg)).map(
+                            move |__res, __self, __ctx| {
+                                let result = __res;
+                                result.unwrap()
+                            },
+                        )
+                    },
+                ),
+            )
+        }
+    }
+    mod nested {
+        impl actix::Handler<Pong> for AnActor {
+            type Result = actix::ResponseActFuture<Self, u64>;
+            fn handle(&mut self, msg: Pong, ctx: &mut Self::Context) -> Self::Result {
+                use actix::ActorFutureExt;
+                Box::pin(
+                    actix::fut::wrap_future::<_, Self>(actix::fut::ready(())).then(
+                        move |__res, __self, __ctx| {
+                            actix::fut::wrap_future::<_, Self>(__self.delegate.send(msg)).map(
+                                move |__res, __self, __ctx| {
+                                    let result = __res;
+                                    result.unwrap()
+                                },
+                            )
+                        },
+                    ),
+                )
+            }
+        }
+    }
+}
+"#;
+
+    let actual = rust_format::RustFmt::default().format_tokens(result.clone().expect("")).expect("");
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_actor_interface() {
+    let result = actor_interface_inner(&HandlerArgs::default(), quote! {
+        pub trait Counter {
+            /// Adds to the count
+            async fn incr_by(&mut self, by: u64) -> u64;
+            async fn checked_reset(&mut self) -> Result<(), CounterError>;
+        }
+    });
+
+    let expected =
+        r#"#[doc = r" Adds to the count"]
+pub struct IncrBy {
+    pub by: u64,
+}
+impl actix::Message for IncrBy {
+    type Result = u64;
+}
+pub struct CheckedReset {}
+impl actix::Message for CheckedReset {
+    type Result = Result<(), CounterError>;
+}
+pub trait Counter: actix::Actor {
+    #[doc = r" Adds to the count"]
+    fn incr_by(
+        &mut self,
+        by: u64,
+        ctx: &mut <Self as actix::Actor>::Context,
+    ) -> actix::ResponseActFuture<Self, u64>;
+    fn checked_reset(
+        &mut self,
+        ctx: &mut <Self as actix::Actor>::Context,
+    ) -> actix::ResponseActFuture<Self, Result<(), CounterError>>;
+}
+#[doc = "Sends the [`Counter`] messages to the address of an actor handling them"]
+pub trait CounterClient {
+    #[doc = r" Adds to the count"]
+    fn incr_by(
+        &self,
+        by: u64,
+    ) -> impl std::future::Future<Output = std::result::Result<u64, actix::MailboxError>>;
+    fn checked_reset(
+        &self,
+    ) -> impl std::future::Future<Output = std::result::Result<(), CounterError>>;
+}
+impl<A: actix::Actor> CounterClient for actix::Addr<A>
+where
+    A: actix::Handler<IncrBy>,
+    A::Context: actix::dev::ToEnvelope<A, IncrBy>,
+    A: actix::Handler<CheckedReset>,
+    A::Context: actix::dev::ToEnvelope<A, CheckedReset>,
+{
+    fn incr_by(
+        &self,
+        by: u64,
+    ) -> impl std::future::Future<Output = std::result::Result<u64, actix::MailboxError>> {
+        let request = self.send(IncrBy { by });
+        request
+    }
+    fn checked_reset(
+        &self,
+    ) -> impl std::future::Future<Output = std::result::Result<(), CounterError>> {
+        let request = self.send(CheckedReset {});
+        async move {
+            match request.await {
+                Ok(result) => result,
+                Err(error) => Err(error.into()),
+            }
+        }
+    }
+}
+"#;
+
+    let actual = rust_format::RustFmt::default().format_tokens(result.clone().expect("")).expect("");
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_actor_interface_impl() {
+    let result = actor_interface_inner(&HandlerArgs::default(), quote! {
+        impl api::Counter for AnActor {
+            async fn incr_by(&mut self, mut by: u64, ctx: &mut Self::Context) -> u64 {
+                let result = self.delegate.send(Ping).await;
+                by += result.unwrap();
+                by
+            }
+
+            fn checked_reset(&mut self) -> Result<(), CounterError> {
+                Ok(())
+            }
+        }
+    });
+
+    let expected =
+        r#"impl api::Counter for AnActor {
+    fn incr_by(
+        &mut self,
+        mut by: u64,
+        ctx: &mut <Self as actix::Actor>::Context,
+    ) -> actix::ResponseActFuture<Self, u64> {
+        use actix::ActorFutureExt;
+        Box::pin(
+            actix::fut::wrap_future::<_, Self>(actix::fut::ready(())).then(
+                move |__res, __self, __ctx| {
+                    actix::fut::wrap_future::<_, Self>(__self.delegate.send(Ping)).map(
+                        move |__res, __self, __ctx| {
+                            let result = __res;
+                            by += result.unwrap();
+                            by
+                        },
+                    )
+                },
+            ),
+        )
+    }
+    fn checked_reset(
+        &mut self,
+        __ctx: &mut <Self as actix::Actor>::Context,
+    ) -> actix::ResponseActFuture<Self, Result<(), CounterError>> {
+        use actix::ActorFutureExt;
+        Box::pin(
+            actix::fut::wrap_future::<_, Self>(actix::fut::ready(()))
+                .map(move |__res, __self, __ctx| Ok(())),
+        )
+    }
+}
+impl actix::Handler<api::IncrBy> for AnActor {
+    type Result = actix::AtomicResponse<Self, u64>;
+    fn handle(&mut self, __msg: api::IncrBy, __ctx: &mut Self::Context) -> Self::Result {
+        actix::AtomicResponse::new(<Self as api::Counter>::incr_by(self, __msg.by, __ctx))
+    }
+}
+impl actix::Handler<api::CheckedReset> for AnActor {
+    type Result = actix::AtomicResponse<Self, Result<(), CounterError>>;
+    fn handle(&mut self, __msg: api::CheckedReset, __ctx: &mut Self::Context) -> Self::Result {
+        actix::AtomicResponse::new(<Self as api::Counter>::checked_reset(self, __ctx))
+    }
+}
+"#;
+
+    let actual = rust_format::RustFmt::default().format_tokens(result.clone().expect("")).expect("");
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_actor_fn() {
+    let result = actor_fn_inner(&HandlerArgs::default(), &Translation::default(), quote! {
+        pub fn refresh(&mut self, key: u64) -> Value {
+            let value = self.store.send(Get(key)).await;
+            let value = actor_await!(self.validate(value.unwrap()));
+            self.cache.insert(key, value.clone());
+            value
+        }
+    });
+
+    let expected =
+        r#"pub fn refresh(&mut self, key: u64) -> impl actix::ActorFuture<Self, Output = Value> {
+    use actix::ActorFutureExt;
+    actix::fut::wrap_future::<_, Self>(actix::fut::ready(())).then(move |__res, __self, __ctx| {
+        actix::fut::wrap_future::<_, Self>(__self.store.send(Get(key))).then(
+            move |__res, __self, __ctx| {
+                let value = __res;
+                __self
+                    .validate(value.unwrap())
+                    .map(move |__res, __self, __ctx| {
+                        let value = __res;
+                        __self.cache.insert(key, value.clone());
+                        value
+                    })
+            },
+        )
+    })
+}
+"#;
+
+    let actual = rust_format::RustFmt::default().format_tokens(result.clone().expect("")).expect("");
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_awaits_actor_futures() {
+    let result = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
+        impl Handler<Ping> for AnActor {
+            type Result = u64;
+            async fn handle(&mut self, msg: Ping, ctx: &mut Self::Context) -> Self::Result {
+                let count = actor_await!(self.delegate.send(msg).into_actor(self).map(|res, this, _| this.base + res.unwrap()));
+                let sum = self.delegate.send(Ping).into_actor(self).await;
+                actor_await!(if count > 1 { self.flush(count) } else { self.flush(0) });
+                count + sum.unwrap()
+            }
+        }
+    });
+
+    let expected =
+        r#"impl Handler<Ping> for AnActor {
+    type Result = actix::AtomicResponse<Self, u64>;
+    fn handle(&mut self, msg: Ping, ctx: &mut Self::Context) -> Self::Result {
+        use actix::ActorFutureExt;
+        actix::AtomicResponse::new(Box::pin(
+            actix::fut::wrap_future::<_, Self>(actix::fut::ready(())).then(
+                move |__res, __self, __ctx| {
+                    __self
+                        .delegate
+                        .send(msg)
+                        .into_actor(__self)
+                        .map(|res, this, _| this.base + res.unwrap())
+                        .then(move |__res, __self, __ctx| {
+                            let count = __res;
+                            __self.delegate.send(Ping).into_actor(__self).then(
+                                move |__res, __self, __ctx| {
+                                    let sum = __res;
+                                    (if count > 1 {
+                                        __self.flush(count)
+                                    } else {
+                                        __self.flush(0)
+                                    })
+                                    .map(move |__res, __self, __ctx| count + sum.unwrap())
+                                },
+                            )
+                        })
+                },
+            ),
+        ))
+    }
+}
+"#;
+
+    let actual = rust_format::RustFmt::default().format_tokens(result.clone().expect("")).expect("");
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_inline_handlers() {
+    let args = HandlerArgs { inline: true, ..Default::default() };
+    let result = async_handler_inner(&args, &Translation::default(), quote! {
+        impl Handler<Refresh> for AnActor {
+            type Result = u64;
+            async fn handle(&mut self, Refresh(key): Refresh, ctx: &mut Self::Context) -> Self::Result {
+                let value = self.store.send(Get(key)).await;
+                let total = self.handle_inline(Total, ctx).await;
+                let count = self.handle_inline::<Count>(Count::new(total), ctx).await;
+                value.unwrap() + count
+            }
+        }
+    });
+
+    let expected =
+        r#"impl Handler<Refresh> for AnActor {
+    type Result = actix::AtomicResponse<Self, u64>;
+    fn handle(&mut self, __msg: Refresh, __ctx: &mut Self::Context) -> Self::Result {
+        actix::AtomicResponse::new(Box::pin(self.__handle_refresh(__msg, __ctx)))
+    }
+}
+impl AnActor {
+    fn __handle_refresh(
+        &mut self,
+        Refresh(key): Refresh,
+        __ctx: &mut <Self as actix::Actor>::Context,
+    ) -> impl actix::ActorFuture<Self, Output = u64> {
+        use actix::ActorFutureExt;
+        actix::fut::wrap_future::<_, Self>(actix::fut::ready(())).then(
+            move |__res, __self, __ctx| {
+                actix::fut::wrap_future::<_, Self>(__self.store.send(Get(key))).then(
+                    move |__res, __self, __ctx| {
+                        let value = __res;
+                        __self
+                            .__handle_total(Total, __ctx)
+                            .then(move |__res, __self, __ctx| {
+                                let total = __res;
+                                __self.__handle_count(Count::new(total), __ctx).map(
+                                    move |__res, __self, __ctx| {
+                                        let count = __res;
+                                        value.unwrap() + count
+                                    },
+                                )
+                            })
+                    },
+                )
+            },
+        )
+    }
+}
+"#;
+
+    let actual = rust_format::RustFmt::default().format_tokens(result.clone().expect("")).expect("");
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_async_actor() {
+    let result = async_actor_inner(&HandlerArgs::default(), quote! {
+        impl Actor for AnActor {
+            type Context = Context<Self>;
+
+            async fn started(&mut self, ctx: &mut Self::Context) {
+                let state = self.store.send(Load).await;
+                self.state = state.unwrap();
+                ctx.notify(Tick);
+            }
+
+            async fn stopping(&mut self, _: &mut Self::Context) -> Running {
+                let saved = self.store.send(Save(self.state)).await;
+                if saved.is_ok() { Running::Stop } else { Running::Continue }
+            }
+        }
+    });
+
+    let expected =
+        r#"impl Actor for AnActor {
+    type Context = Context<Self>;
+    fn started(&mut self, __ctx: &mut Self::Context) {
+        use actix::{ActorFutureExt, AsyncContext};
+        __ctx.wait(
+            actix::fut::wrap_future::<_, Self>(actix::fut::ready(())).then(
+                move |__res, __self, __ctx| {
+                    actix::fut::wrap_future::<_, Self>(__self.store.send(Load)).map(
+                        move |__res, __self, __ctx| {
+                            let state = __res;
+                            __self.state = state.unwrap();
+                            __ctx.notify(Tick);
+                        },
+                    )
+                },
+            ),
+        );
+    }
+    fn stopping(&mut self, __ctx: &mut Self::Context) -> Running {
+        use actix::{ActorContext, ActorFutureExt, AsyncContext};
+        let __closed = __ctx.state() == actix::ActorState::Running;
+        __ctx.wait(
+            actix::fut::wrap_future::<_, Self>(actix::fut::ready(()))
+                .then(move |__res, __self, __ctx| {
+                    actix::fut::wrap_future::<_, Self>(__self.store.send(Save(__self.state))).map(
+                        move |__res, __self, __ctx| {
+                            let saved = __res;
+                            if saved.is_ok() {
+                                Running::Stop
+                            } else {
+                                Running::Continue
+                            }
+                        },
+                    )
+                })
+                .map(move |__res, _, __ctx: &mut Self::Context| {
+                    if __res == actix::Running::Stop || __closed {
+                        __ctx.terminate();
+                    }
+                }),
+        );
+        if __closed {
+            let _ = __ctx.address();
+        }
+        actix::Running::Continue
+    }
+}
+"#;
+
+    let actual = rust_format::RustFmt::default().format_tokens(result.clone().expect("")).expect("");
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_stream_handler() {
+    let result = async_handler_inner(&HandlerArgs::default(), &Translation::default(), quote! {
+        impl StreamHandler<Event> for AnActor {
+            async fn handle(&mut self, event: Event, ctx: &mut Self::Context) {
+                let stored = self.store.send(event).await;
+                self.stored += stored.unwrap();
+            }
+
+            async fn finished(&mut self, ctx: &mut Self::Context) {
+                self.store.send(Flush).await;
+                ctx.stop();
+            }
+        }
+    });
+
+    let expected =
+        r#"impl StreamHandler<Event> for AnActor {
+    fn handle(&mut self, event: Event, __ctx: &mut Self::Context) {
+        use actix::{ActorFutureExt, AsyncContext};
+        __ctx.wait(
+            actix::fut::wrap_future::<_, Self>(actix::fut::ready(())).then(
+                move |__res, __self, __ctx| {
+                    actix::fut::wrap_future::<_, Self>(__self.store.send(event)).map(
+                        move |__res, __self, __ctx| {
+                            let stored = __res;
+                            __self.stored += stored.unwrap();
+                        },
+                    )
+                },
+            ),
+        );
+    }
+    fn finished(&mut self, __ctx: &mut Self::Context) {
+        use actix::{ActorFutureExt, AsyncContext};
+        __ctx.wait(
+            actix::fut::wrap_future::<_, Self>(actix::fut::ready(())).then(
+                move |__res, __self, __ctx| {
+                    actix::fut::wrap_future::<_, Self>(__self.store.send(Flush)).map(
+                        move |__res, __self, __ctx| {
+                            __ctx.stop();
+                        },
+                    )
+                },
+            ),
+        );
+    }
+}
+"#;
+
+    let actual = rust_format::RustFmt::default().format_tokens(result.clone().expect("")).expect("");
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_stream_handler_non_atomic() {
+    let result = async_handler_inner(&HandlerArgs { is_atomic: false, ..Default::default() }, &Translation::default(), quote! {
+        impl StreamHandler<Event> for AnActor {
+            async fn handle(&mut self, event: Event, _: &mut Self::Context) {
+                self.store.send(event).await;
+            }
+        }
+    });
+
+    let expected =
+        r#"impl StreamHandler<Event> for AnActor {
+    fn handle(&mut self, event: Event, __ctx: &mut Self::Context) {
+        use actix::{ActorFutureExt, AsyncContext};
+        __ctx.spawn(
+            actix::fut::wrap_future::<_, Self>(actix::fut::ready(()))
+                .then(move |__res, __self, __ctx| {
+                    actix::fut::wrap_future::<_, Self>(__self.store.send(event))
+                })
+                .map(|_, _, _| {}),
+        );
+    }
+}
+"#;
+
+    let actual = rust_format::RustFmt::default().format_tokens(result.clone().expect("")).expect("");
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_async_restarting() {
+    let result = async_actor_inner(&HandlerArgs::default(), quote! {
+        impl actix::Supervised for AnActor {
+            async fn restarting(&mut self, ctx: &mut Context<Self>) {
+                let registered = self.registry.send(Register(ctx.address())).await;
+                self.id = registered.unwrap();
+            }
+        }
+    });
+
+    let expected =
+        r#"impl actix::Supervised for AnActor {
+    fn restarting(&mut self, __ctx: &mut Context<Self>) {
+        use actix::{ActorFutureExt, AsyncContext};
+        __ctx.wait(
+            actix::fut::wrap_future::<_, Self>(actix::fut::ready(())).then(
+                move |__res, __self, __ctx| {
+                    actix::fut::wrap_future::<_, Self>(
+                        __self.registry.send(Register(__ctx.address())),
+                    )
+                    .map(move |__res, __self, __ctx| {
+                        let registered = __res;
+                        __self.id = registered.unwrap();
+                    })
+                },
+            ),
+        );
+    }
+}
+"#;
+
+    let actual = rust_format::RustFmt::default().format_tokens(result.clone().expect("")).expect("");
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_actor_async() {
+    let result = actor_async_inner(&HandlerArgs::default(), quote! {
+        self, ctx, {
+            let result = self.delegate.send(Count(msg.0)).await;
+            self.total += result.unwrap();
+            ctx.notify(Refresh(0));
+        }
+    });
+
+    let expected = r#"fn refresh() {
+    let _ = &ctx;
+    use actix::ActorFutureExt;
+    actix::fut::wrap_future::<_, Self>(actix::fut::ready(())).then(move |__res, __self, __ctx| {
+        actix::fut::wrap_future::<_, Self>(__self.delegate.send(Count(msg.0))).map(
+            move |__res, __self, __ctx| {
+                let result = __res;
+                __self.total += result.unwrap();
+                __ctx.notify(Refresh(0));
+            },
+        )
+    })
+}
+"#;
+
+    // the expansion is an expression, formatted as the body of a function
+    let future = result.expect("");
+    let actual = rust_format::RustFmt::default().format_tokens(quote!(fn refresh() #future)).expect("");
+    assert_eq!(expected, actual);
+}
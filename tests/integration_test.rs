@@ -2,7 +2,7 @@ use actix::{Addr, Handler};
 
 use actix::{Actor, Context, Message};
 
-use actix_async_handler::async_handler;
+use actix_async_handler::{actor_async, actor_fn, actor_interface, async_actor, async_handler, async_handlers};
 
 struct Counter {}
 
@@ -17,7 +17,7 @@ struct Count(u64);
 impl Handler<Count> for Counter {
     type Result = u64;
 
-    fn handle(&mut self, msg: Count, ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: Count, _ctx: &mut Self::Context) -> Self::Result {
         msg.0 + 1
     }
 }
@@ -37,7 +37,7 @@ async fn test_awaits_and_variables() {
     impl Handler<Count> for AnActor {
         type Result = u64;
 
-        async fn handle(&mut self, msg: Count, ctx: &mut Self::Context) -> Self::Result {
+        async fn handle(&mut self, msg: Count, _ctx: &mut Self::Context) -> Self::Result {
             let mut count = msg.0;
             let result = self.delegate.send(Count(count)).await;
             count += result.unwrap();
@@ -60,7 +60,7 @@ async fn test_awaits_and_variables() {
 async fn test_no_awaits() {
 
     struct AnActor {
-        delegate: Addr<Counter>
+        _delegate: Addr<Counter>
     }
 
     impl Actor for AnActor {
@@ -71,13 +71,13 @@ async fn test_no_awaits() {
     impl Handler<Count> for AnActor {
         type Result = u64;
 
-        async fn handle(&mut self, msg: Count, ctx: &mut Self::Context) -> Self::Result {
+        async fn handle(&mut self, msg: Count, _ctx: &mut Self::Context) -> Self::Result {
             msg.0 + 1
         }
     }
 
     let delegate = Counter {}.start();
-    let addr = AnActor { delegate }.start();
+    let addr = AnActor { _delegate: delegate }.start();
     let result = addr.send(Count(1)).await.unwrap();
     assert_eq!(2, result);
 }
@@ -99,7 +99,7 @@ async fn test_ifs_part1() {
     impl Handler<Count> for AnActor {
         type Result = u64;
 
-        async fn handle(&mut self, msg: Count, ctx: &mut Self::Context) -> Self::Result {
+        async fn handle(&mut self, msg: Count, _ctx: &mut Self::Context) -> Self::Result {
 
             self.flags = 0;
 
@@ -188,7 +188,7 @@ async fn test_ifs_part1() {
     let addr = AnActor { delegate, flags: 0 }.start();
     for i in 0..20 {
         let result = addr.send(Count(i)).await.unwrap();
-        assert_eq!(((1 << i) & result) > 0, true, "case {} value {}", i, result);
+        assert!(((1 << i) & result) > 0, "case {} value {}", i, result);
     }
 
 }
@@ -209,7 +209,7 @@ async fn test_ifs_part2() {
     impl Handler<Count> for AnActor {
         type Result = u64;
 
-        async fn handle(&mut self, msg: Count, ctx: &mut Self::Context) -> Self::Result {
+        async fn handle(&mut self, msg: Count, _ctx: &mut Self::Context) -> Self::Result {
 
             self.flags = 0;
 
@@ -288,7 +288,7 @@ async fn test_ifs_part2() {
     let addr = AnActor { delegate, flags: 0 }.start();
     for i in 20..38 {
         let result = addr.send(Count(i)).await.unwrap();
-        assert_eq!(((1 << i) & result) > 0, true, "case {} value {}", i, result);
+        assert!(((1 << i) & result) > 0, "case {} value {}", i, result);
     }
 
 }
@@ -308,7 +308,7 @@ async fn test_ifs_return_values() {
     impl Handler<Count> for AnActor {
         type Result = u64;
 
-        async fn handle(&mut self, msg: Count, ctx: &mut Self::Context) -> Self::Result {
+        async fn handle(&mut self, msg: Count, _ctx: &mut Self::Context) -> Self::Result {
 
             let mut ret1 = 0;
 
@@ -357,7 +357,7 @@ async fn test_for_loop() {
     impl Handler<Count> for AnActor {
         type Result = u64;
 
-        async fn handle(&mut self, msg: Count, ctx: &mut Self::Context) -> Self::Result {
+        async fn handle(&mut self, msg: Count, _ctx: &mut Self::Context) -> Self::Result {
 
             let mut i = 0;
             i = for delegate in self.delegates.clone() {
@@ -376,7 +376,7 @@ async fn test_for_loop() {
     }
 
     let mut delegates = vec![];
-    for id in 0..5 {
+    for _id in 0..5 {
         delegates.push(Counter {}.start());
     };
 
@@ -384,4 +384,1044 @@ async fn test_for_loop() {
     let result = addr.send(Count(0)).await.unwrap();
     assert_eq!(result, 20);
 
-}
\ No newline at end of file
+}
+
+#[actix_rt::test]
+async fn test_for_loop_user_items() {
+
+    struct IterStream(Vec<Addr<Counter>>);
+
+    struct AnActor {
+        acc: u64,
+        delegates: Vec<Addr<Counter>>
+    }
+
+    impl Actor for AnActor {
+        type Context = Context<Self>;
+    }
+
+    #[async_handler]
+    impl Handler<Count> for AnActor {
+        type Result = u64;
+
+        async fn handle(&mut self, msg: Count, _ctx: &mut Self::Context) -> Self::Result {
+            for delegate in IterStream(self.delegates.clone()).0 {
+                let r = delegate.send(Count(msg.0)).await;
+                self.acc += r.unwrap();
+            };
+            self.acc
+        }
+    }
+
+    let delegates = (0..3).map(|_| Counter {}.start()).collect();
+    let addr = AnActor { delegates, acc: 0 }.start();
+    let result = addr.send(Count(2)).await.unwrap();
+    assert_eq!(result, 9);
+
+}
+
+#[actix_rt::test]
+async fn test_non_atomic() {
+
+    struct AnActor {
+        delegate: Addr<Counter>
+    }
+
+    impl Actor for AnActor {
+        type Context = Context<Self>;
+    }
+
+    #[async_handler(non_atomic)]
+    impl Handler<Count> for AnActor {
+        type Result = u64;
+
+        async fn handle(&mut self, msg: Count, _ctx: &mut Self::Context) -> Self::Result {
+            let result = self.delegate.send(Count(msg.0)).await;
+            result.unwrap() * 2
+        }
+    }
+
+    let delegate = Counter {}.start();
+    let addr = AnActor { delegate }.start();
+    let result = addr.send(Count(1)).await.unwrap();
+    assert_eq!(4, result);
+}
+
+mod facade {
+    pub use actix as reactor;
+}
+
+#[actix_rt::test]
+async fn test_crate_paths() {
+
+    struct AnActor {
+        delegates: Vec<Addr<Counter>>
+    }
+
+    impl Actor for AnActor {
+        type Context = Context<Self>;
+    }
+
+    #[async_handler(crate = crate::facade::reactor)]
+    impl Handler<Count> for AnActor {
+        type Result = u64;
+
+        async fn handle(&mut self, msg: Count, _ctx: &mut Self::Context) -> Self::Result {
+            let mut total = 0;
+            total = for delegate in self.delegates.clone() {
+                let r = delegate.send(Count(msg.0)).await;
+                total += r.unwrap();
+            };
+            total
+        }
+    }
+
+    let delegates = vec![Counter {}.start(), Counter {}.start()];
+    let addr = AnActor { delegates }.start();
+    let result = addr.send(Count(1)).await.unwrap();
+    assert_eq!(4, result);
+}
+
+trait Numbered: Message<Result = u64> {
+    fn number(&self) -> u64;
+}
+
+impl Numbered for Count {
+    fn number(&self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "u64")]
+struct Double(u64);
+
+impl Numbered for Double {
+    fn number(&self) -> u64 {
+        self.0 * 2
+    }
+}
+
+#[actix_rt::test]
+async fn test_generic_impl() {
+
+    use actix::Handler as Handles;
+
+    struct Router {
+        delegate: Addr<Counter>
+    }
+
+    impl Actor for Router {
+        type Context = Context<Self>;
+    }
+
+    #[async_handler(trait = Handles)]
+    impl<M: Numbered + 'static> Handles<M> for Router {
+        type Result = u64;
+
+        async fn handle(&mut self, msg: M, _ctx: &mut Self::Context) -> Self::Result {
+            let result = self.delegate.send(Count(msg.number())).await;
+            result.unwrap()
+        }
+    }
+
+    let addr = Router { delegate: Counter {}.start() }.start();
+    assert_eq!(2, addr.send(Count(1)).await.unwrap());
+    assert_eq!(3, addr.send(Double(1)).await.unwrap());
+}
+
+#[actix_rt::test]
+async fn test_inferred_result_type() {
+
+    struct AnActor {
+        delegate: Addr<Counter>
+    }
+
+    impl Actor for AnActor {
+        type Context = Context<Self>;
+    }
+
+    #[async_handler]
+    impl Handler<Count> for AnActor {
+        async fn handle(&mut self, msg: Count, _ctx: &mut Context<Self>) -> u64 {
+            let result = self.delegate.send(msg).await;
+            result.unwrap()
+        }
+    }
+
+    let addr = AnActor { delegate: Counter {}.start() }.start();
+    assert_eq!(2, addr.send(Count(1)).await.unwrap());
+}
+
+#[actix_rt::test]
+async fn test_parameter_patterns() {
+
+    struct AnActor {
+        delegate: Addr<Counter>
+    }
+
+    impl Actor for AnActor {
+        type Context = Context<Self>;
+    }
+
+    #[async_handler]
+    impl Handler<Count> for AnActor {
+        type Result = u64;
+
+        async fn handle(&mut self, Count(n): Count, _: &mut Self::Context) -> Self::Result {
+            let first = self.delegate.send(Count(n)).await;
+            let second = self.delegate.send(Count(n)).await;
+            first.unwrap() + second.unwrap() + n
+        }
+    }
+
+    let addr = AnActor { delegate: Counter {}.start() }.start();
+    assert_eq!(5, addr.send(Count(1)).await.unwrap());
+}
+
+#[actix_rt::test]
+async fn test_renames_params_in_scope_only() {
+
+    use actix::AsyncContext;
+
+    struct AnActor {
+        delegate: Addr<Counter>,
+        ctx: u64
+    }
+
+    impl Actor for AnActor {
+        type Context = Context<Self>;
+    }
+
+    #[async_handler]
+    impl Handler<Count> for AnActor {
+        type Result = u64;
+
+        async fn handle(&mut self, msg: Count, ctx: &mut Self::Context) -> Self::Result {
+            let add = |ctx: u64| ctx + 1;
+            let result = self.delegate.send(msg).await;
+            assert!(ctx.address().connected());
+            add(result.unwrap()) + self.ctx
+        }
+    }
+
+    let addr = AnActor { delegate: Counter {}.start(), ctx: 10 }.start();
+    assert_eq!(13, addr.send(Count(1)).await.unwrap());
+}
+
+#[actix_rt::test]
+async fn test_renames_params_in_macros() {
+
+    #[derive(Message)]
+    #[rtype(result = "String")]
+    struct Describe(u64);
+
+    #[derive(Debug)]
+    struct AnActor {
+        sent: Vec<u64>
+    }
+
+    impl Actor for AnActor {
+        type Context = Context<Self>;
+    }
+
+    #[async_handler]
+    impl Handler<Describe> for AnActor {
+        type Result = String;
+
+        async fn handle(&mut self, msg: Describe, ctx: &mut Self::Context) -> Self::Result {
+            let counter = Counter {}.start();
+            let local = 5;
+            let result = counter.send(Count(msg.0)).await;
+            self.sent.push(result.unwrap());
+            assert!(matches!(self.sent.last(), Some(2)));
+            let mut all = vec![(self.sent.len() as u64)];
+            all.push(self.sent[0]);
+            assert_eq!(all, [1, 2]);
+            assert!(!format!("{ctx:p}").is_empty());
+            format!("{self:?} {local} {}", (self.sent[0]))
+        }
+    }
+
+    let addr = AnActor { sent: vec![] }.start();
+    assert_eq!("AnActor { sent: [2] } 5 2", addr.send(Describe(1)).await.unwrap());
+}
+
+#[actix_rt::test]
+async fn test_internal_idents_are_hygienic() {
+
+    struct AnActor {
+        delegate: Addr<Counter>
+    }
+
+    impl Actor for AnActor {
+        type Context = Context<Self>;
+    }
+
+    #[async_handler]
+    impl Handler<Count> for AnActor {
+        type Result = u64;
+
+        async fn handle(&mut self, msg: Count, _ctx: &mut Self::Context) -> Self::Result {
+            let __res = 10;
+            let __acc = 100;
+            let delegate = self.delegate.clone();
+            let mut total = 0;
+            total = for i in 0..2 {
+                let r = delegate.send(Count(i)).await;
+                total += r.unwrap();
+            };
+            let result = self.delegate.send(msg).await;
+            result.unwrap() + total + __res + __acc
+        }
+    }
+
+    let addr = AnActor { delegate: Counter {}.start() }.start();
+    assert_eq!(2 + 3 + 10 + 100, addr.send(Count(1)).await.unwrap());
+}
+
+#[actix_rt::test]
+async fn test_loop_tuple_accumulator() {
+
+    struct AnActor {
+        delegates: Vec<Addr<Counter>>
+    }
+
+    impl Actor for AnActor {
+        type Context = Context<Self>;
+    }
+
+    #[async_handler]
+    impl Handler<Count> for AnActor {
+        type Result = u64;
+
+        async fn handle(&mut self, msg: Count, _ctx: &mut Self::Context) -> Self::Result {
+            let (mut sent, mut total) = (0, 0);
+            (sent, total) = for delegate in self.delegates.clone() {
+                let r = delegate.send(Count(msg.0)).await;
+                sent += 1;
+                total += r.unwrap();
+            };
+            sent * 100 + total
+        }
+    }
+
+    let delegates = vec![Counter {}.start(), Counter {}.start()];
+    let addr = AnActor { delegates }.start();
+    assert_eq!(204, addr.send(Count(1)).await.unwrap());
+}
+
+#[actix_rt::test]
+async fn test_statement_attributes() {
+
+    struct AnActor {
+        delegate: Addr<Counter>,
+        sent: u64
+    }
+
+    impl Actor for AnActor {
+        type Context = Context<Self>;
+    }
+
+    #[async_handler]
+    impl Handler<Count> for AnActor {
+        type Result = u64;
+
+        async fn handle(&mut self, msg: Count, _ctx: &mut Self::Context) -> Self::Result {
+            #[cfg(not(test))]
+            self.delegate.send(Count(100)).await;
+            self.sent += 1;
+            #[cfg(test)]
+            let result = self.delegate.send(msg).await;
+            #[allow(unused_variables)]
+            let unused = self.delegate.send(msg).await;
+            self.sent * 10 + result.unwrap()
+        }
+    }
+
+    let addr = AnActor { delegate: Counter {}.start(), sent: 0 }.start();
+    assert_eq!(12, addr.send(Count(1)).await.unwrap());
+}
+
+#[actix_rt::test]
+async fn test_trailing_await() {
+
+    #[derive(Message)]
+    #[rtype(result = "()")]
+    struct Forward(u64);
+
+    struct AnActor {
+        delegate: Addr<Counter>,
+        forwarded: u64
+    }
+
+    impl Actor for AnActor {
+        type Context = Context<Self>;
+    }
+
+    #[async_handler]
+    impl Handler<Forward> for AnActor {
+        type Result = ();
+
+        async fn handle(&mut self, msg: Forward, _ctx: &mut Self::Context) {
+            self.forwarded += 1;
+            self.delegate.send(Count(msg.0)).await;
+        }
+    }
+
+    #[async_handler]
+    impl Handler<Count> for AnActor {
+        type Result = u64;
+
+        fn handle(&mut self, _msg: Count, _ctx: &mut Self::Context) -> u64 {
+            self.forwarded
+        }
+    }
+
+    let addr = AnActor { delegate: Counter {}.start(), forwarded: 0 }.start();
+    addr.send(Forward(1)).await.unwrap();
+    assert_eq!(1, addr.send(Count(0)).await.unwrap());
+}
+
+#[actix_rt::test]
+async fn test_sync_handler() {
+
+    struct Pong(u64);
+
+    #[derive(Message)]
+    #[rtype(result = "Pong")]
+    struct Ping(u64);
+
+    struct AnActor {
+        pings: u64
+    }
+
+    impl Actor for AnActor {
+        type Context = Context<Self>;
+    }
+
+    #[async_handler]
+    impl Handler<Ping> for AnActor {
+        fn handle(&mut self, msg: Ping, _ctx: &mut Self::Context) -> Pong {
+            self.pings += 1;
+            if msg.0 == 0 {
+                return Pong(0);
+            }
+            Pong(msg.0 + self.pings)
+        }
+    }
+
+    let addr = AnActor { pings: 0 }.start();
+    assert_eq!(0, addr.send(Ping(0)).await.unwrap().0);
+    assert_eq!(12, addr.send(Ping(10)).await.unwrap().0);
+}
+
+#[actix_rt::test]
+async fn test_declared_response_type() {
+
+    use actix::ResponseActFuture;
+
+    struct AnActor {
+        delegate: Addr<Counter>
+    }
+
+    impl Actor for AnActor {
+        type Context = Context<Self>;
+    }
+
+    #[async_handler]
+    impl Handler<Count> for AnActor {
+        type Result = ResponseActFuture<Self, u64>;
+
+        async fn handle(&mut self, msg: Count, _ctx: &mut Self::Context) -> Self::Result {
+            let result = self.delegate.send(msg).await;
+            result.unwrap()
+        }
+    }
+
+    let addr = AnActor { delegate: Counter {}.start() }.start();
+    assert_eq!(2, addr.send(Count(1)).await.unwrap());
+}
+
+#[actix_rt::test]
+async fn test_async_handlers() {
+
+    #[derive(Message)]
+    #[rtype(result = "u64")]
+    struct Total;
+
+    struct AnActor {
+        delegate: Addr<Counter>,
+        total: u64
+    }
+
+    impl Actor for AnActor {
+        type Context = Context<Self>;
+    }
+
+    #[async_handlers]
+    impl AnActor {
+        async fn count(&mut self, msg: Count, _ctx: &mut Context<Self>) -> u64 {
+            let result = self.delegate.send(msg).await;
+            self.total += result.unwrap();
+            self.total
+        }
+
+        #[non_atomic]
+        async fn total(&mut self, _msg: Total, _ctx: &mut Context<Self>) -> u64 {
+            let result = self.delegate.send(Count(0)).await;
+            self.doubled() + result.unwrap()
+        }
+
+        fn doubled(&self) -> u64 {
+            self.total * 2
+        }
+    }
+
+    let addr = AnActor { delegate: Counter {}.start(), total: 0 }.start();
+    assert_eq!(2, addr.send(Count(1)).await.unwrap());
+    assert_eq!(5, addr.send(Count(2)).await.unwrap());
+    assert_eq!(11, addr.send(Total).await.unwrap());
+}
+
+struct ModuleActor {
+    delegate: Addr<Counter>
+}
+
+impl Actor for ModuleActor {
+    type Context = Context<Self>;
+}
+
+#[derive(Message)]
+#[rtype(result = "u64")]
+struct SyncPing(u64);
+
+#[async_handlers(non_atomic)]
+mod module_handlers {
+    use actix::{Handler, Context};
+
+    use super::{Count, ModuleActor, SyncPing};
+
+    impl Handler<Count> for ModuleActor {
+        type Result = u64;
+
+        async fn handle(&mut self, msg: Count, _ctx: &mut Context<Self>) -> Self::Result {
+            let result = self.delegate.send(msg).await;
+            result.unwrap() * 10
+        }
+    }
+
+    impl Handler<SyncPing> for ModuleActor {
+        type Result = u64;
+
+        fn handle(&mut self, msg: SyncPing, _ctx: &mut Context<Self>) -> Self::Result {
+            msg.0
+        }
+    }
+}
+
+#[actix_rt::test]
+async fn test_async_handlers_module() {
+    let addr = ModuleActor { delegate: Counter {}.start() }.start();
+    assert_eq!(20, addr.send(Count(1)).await.unwrap());
+    assert_eq!(7, addr.send(SyncPing(7)).await.unwrap());
+}
+
+mod tally {
+    use actix::MailboxError;
+
+    use actix_async_handler::actor_interface;
+
+    #[derive(Debug, PartialEq)]
+    pub enum TallyError {
+        Underflow,
+        Mailbox
+    }
+
+    impl From<MailboxError> for TallyError {
+        fn from(_: MailboxError) -> Self {
+            TallyError::Mailbox
+        }
+    }
+
+    #[actor_interface]
+    pub trait Tally {
+        /// Adds to the tally, returning the new total
+        async fn add(&mut self, by: u64) -> u64;
+        async fn reset(&mut self);
+        async fn checked_take(&mut self, by: u64) -> Result<u64, TallyError>;
+    }
+}
+
+#[actix_rt::test]
+async fn test_actor_interface() {
+    use tally::{TallyClient, TallyError};
+
+    struct AnActor {
+        delegate: Addr<Counter>,
+        total: u64
+    }
+
+    impl Actor for AnActor {
+        type Context = Context<Self>;
+    }
+
+    #[actor_interface]
+    impl tally::Tally for AnActor {
+        async fn add(&mut self, by: u64) -> u64 {
+            let result = self.delegate.send(Count(by)).await;
+            self.total += result.unwrap() - 1;
+            self.total
+        }
+
+        async fn reset(&mut self, _ctx: &mut Context<Self>) {
+            self.total = 0;
+        }
+
+        #[non_atomic]
+        async fn checked_take(&mut self, by: u64) -> Result<u64, TallyError> {
+            if by > self.total {
+                return Err(TallyError::Underflow)
+            }
+            self.total -= by;
+            Ok(self.total)
+        }
+    }
+
+    fn implements_tally<A: tally::Tally>(_: &Addr<A>) {}
+
+    let addr = AnActor { delegate: Counter {}.start(), total: 0 }.start();
+    implements_tally(&addr);
+    assert_eq!(Ok(3), addr.add(3).await);
+    assert_eq!(Ok(7), addr.add(4).await);
+    assert_eq!(Ok(5), addr.checked_take(2).await);
+    assert_eq!(Err(TallyError::Underflow), addr.checked_take(6).await);
+    assert_eq!(Ok(()), addr.reset().await);
+    assert_eq!(Ok(1), addr.send(tally::Add { by: 1 }).await);
+}
+
+#[actix_rt::test]
+async fn test_actor_fn() {
+
+    struct AnActor {
+        delegate: Addr<Counter>,
+        calls: u64
+    }
+
+    impl Actor for AnActor {
+        type Context = Context<Self>;
+    }
+
+    impl AnActor {
+        #[actor_fn]
+        fn counted(&mut self, n: u64) -> u64 {
+            self.calls += 1;
+            let result = self.delegate.send(Count(n)).await;
+            result.unwrap()
+        }
+
+        #[actor_fn]
+        async fn counted_twice(&mut self, n: u64) -> u64 {
+            let first = self.counted(n).await;
+            self.counted(first).await
+        }
+
+        fn fetch(&self) -> impl std::future::Future<Output = u64> {
+            let calls = self.calls;
+            async move { calls * 100 }
+        }
+    }
+
+    #[async_handler]
+    impl Handler<Count> for AnActor {
+        type Result = u64;
+
+        async fn handle(&mut self, msg: Count, _ctx: &mut Self::Context) -> Self::Result {
+            let result = self.counted_twice(msg.0).await;
+            let fetched = self.fetch().await;
+            fetched + result * 10 + self.calls
+        }
+    }
+
+    let addr = AnActor { delegate: Counter {}.start(), calls: 0 }.start();
+    assert_eq!(232, addr.send(Count(1)).await.unwrap());
+}
+
+#[actix_rt::test]
+async fn test_actor_fns_in_async_handlers() {
+
+    #[derive(Message)]
+    #[rtype(result = "u64")]
+    struct Twice(u64);
+
+    struct AnActor {
+        delegate: Addr<Counter>,
+        calls: u64
+    }
+
+    impl Actor for AnActor {
+        type Context = Context<Self>;
+    }
+
+    #[async_handlers]
+    impl AnActor {
+        #[actor_fn]
+        fn counted(&mut self, n: u64) -> u64 {
+            self.calls += 1;
+            let result = self.delegate.send(Count(n)).await;
+            result.unwrap()
+        }
+
+        #[actor_fn]
+        async fn counted_twice(&mut self, n: u64) -> u64 {
+            let first = self.counted(n).await;
+            self.counted(first).await
+        }
+
+        fn fetch(&self) -> impl std::future::Future<Output = u64> {
+            let calls = self.calls;
+            async move { calls * 100 }
+        }
+
+        async fn twice(&mut self, msg: Twice, _ctx: &mut Context<Self>) -> u64 {
+            let result = self.counted_twice(msg.0).await;
+            let fetched = self.fetch().await;
+            fetched + result * 10 + self.calls
+        }
+    }
+
+    let addr = AnActor { delegate: Counter {}.start(), calls: 0 }.start();
+    assert_eq!(232, addr.send(Twice(1)).await.unwrap());
+}
+
+#[actix_rt::test]
+async fn test_awaits_actor_futures() {
+    use actix::WrapFuture;
+
+    struct AnActor {
+        delegate: Addr<Counter>,
+        base: u64
+    }
+
+    impl Actor for AnActor {
+        type Context = Context<Self>;
+    }
+
+    #[async_handler]
+    impl Handler<Count> for AnActor {
+        type Result = u64;
+
+        async fn handle(&mut self, msg: Count, _ctx: &mut Self::Context) -> Self::Result {
+            let count = actor_await!(self.delegate.send(msg).into_actor(self).map(|res, this, _| this.base + res.unwrap()));
+            let sum = self.delegate.send(Count(count)).into_actor(self).await;
+            sum.unwrap()
+        }
+    }
+
+    let addr = AnActor { delegate: Counter {}.start(), base: 10 }.start();
+    assert_eq!(13, addr.send(Count(1)).await.unwrap());
+}
+
+#[actix_rt::test]
+async fn test_inline_handlers() {
+
+    #[derive(Message)]
+    #[rtype(result = "u64")]
+    struct Twice(u64);
+
+    struct AnActor {
+        delegate: Addr<Counter>,
+        calls: u64
+    }
+
+    impl Actor for AnActor {
+        type Context = Context<Self>;
+    }
+
+    #[async_handler(inline)]
+    impl Handler<Count> for AnActor {
+        type Result = u64;
+
+        async fn handle(&mut self, msg: Count, _ctx: &mut Self::Context) -> Self::Result {
+            self.calls += 1;
+            let result = self.delegate.send(msg).await;
+            result.unwrap()
+        }
+    }
+
+    #[async_handler]
+    impl Handler<Twice> for AnActor {
+        type Result = u64;
+
+        async fn handle(&mut self, msg: Twice, ctx: &mut Self::Context) -> Self::Result {
+            let first = self.handle_inline(Count(msg.0), ctx).await;
+            let second = self.handle_inline(Count(first), ctx).await;
+            second * 10 + self.calls
+        }
+    }
+
+    let addr = AnActor { delegate: Counter {}.start(), calls: 0 }.start();
+    assert_eq!(32, addr.send(Twice(1)).await.unwrap());
+}
+
+#[actix_rt::test]
+async fn test_async_actor() {
+    use std::sync::{Arc, Mutex};
+
+    use actix::Running;
+
+    #[derive(Message)]
+    #[rtype(result = "u64")]
+    struct Loaded;
+
+    struct AnActor {
+        delegate: Addr<Counter>,
+        loaded: u64,
+        log: Arc<Mutex<Vec<u64>>>
+    }
+
+    #[async_actor]
+    impl Actor for AnActor {
+        type Context = Context<Self>;
+
+        async fn started(&mut self, _ctx: &mut Self::Context) {
+            let result = self.delegate.send(Count(41)).await;
+            self.loaded = result.unwrap();
+        }
+
+        async fn stopping(&mut self, _ctx: &mut Self::Context) -> Running {
+            let result = self.delegate.send(Count(self.loaded)).await;
+            self.log.lock().unwrap().push(result.unwrap());
+            Running::Stop
+        }
+
+        fn stopped(&mut self, _ctx: &mut Self::Context) {
+            self.log.lock().unwrap().push(0);
+        }
+    }
+
+    impl Handler<Loaded> for AnActor {
+        type Result = u64;
+
+        fn handle(&mut self, _msg: Loaded, ctx: &mut Self::Context) -> Self::Result {
+            use actix::ActorContext;
+            ctx.stop();
+            self.loaded
+        }
+    }
+
+    let log = Arc::new(Mutex::new(vec![]));
+    let addr = AnActor { delegate: Counter {}.start(), loaded: 0, log: log.clone() }.start();
+    assert_eq!(42, addr.send(Loaded).await.unwrap());
+    while addr.connected() {
+        actix_rt::task::yield_now().await;
+    }
+    assert_eq!(vec![43, 0], *log.lock().unwrap());
+}
+
+#[actix_rt::test]
+async fn test_async_stopping_without_addresses() {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use actix::Running;
+
+    struct AnActor {
+        delegate: Addr<Counter>,
+        log: Arc<Mutex<Vec<u64>>>
+    }
+
+    #[async_actor]
+    impl Actor for AnActor {
+        type Context = Context<Self>;
+
+        async fn stopping(&mut self, _ctx: &mut Self::Context) -> Running {
+            let result = self.delegate.send(Count(1)).await;
+            self.log.lock().unwrap().push(result.unwrap());
+            Running::Continue
+        }
+
+        fn stopped(&mut self, _ctx: &mut Self::Context) {
+            self.log.lock().unwrap().push(0);
+        }
+    }
+
+    impl Drop for AnActor {
+        fn drop(&mut self) {
+            self.log.lock().unwrap().push(100);
+        }
+    }
+
+    let log = Arc::new(Mutex::new(vec![]));
+    drop(AnActor { delegate: Counter {}.start(), log: log.clone() }.start());
+    actix_rt::time::timeout(Duration::from_secs(1), async {
+        while !log.lock().unwrap().contains(&100) {
+            actix_rt::task::yield_now().await;
+        }
+    }).await.expect("the actor to be dropped");
+    assert_eq!(vec![2, 0, 100], *log.lock().unwrap());
+}
+
+struct Numbers(std::vec::IntoIter<u64>);
+
+impl actix::prelude::Stream for Numbers {
+    type Item = u64;
+
+    fn poll_next(mut self: std::pin::Pin<&mut Self>, _: &mut std::task::Context<'_>) -> std::task::Poll<Option<u64>> {
+        std::task::Poll::Ready(self.0.next())
+    }
+}
+
+#[actix_rt::test]
+async fn test_stream_handler() {
+    use std::sync::{Arc, Mutex};
+
+    use actix::{ActorContext, StreamHandler};
+
+    struct AnActor {
+        delegate: Addr<Counter>,
+        log: Arc<Mutex<Vec<u64>>>
+    }
+
+    impl Actor for AnActor {
+        type Context = Context<Self>;
+
+        fn started(&mut self, ctx: &mut Self::Context) {
+            Self::add_stream(Numbers(vec![1, 2, 3].into_iter()), ctx);
+        }
+    }
+
+    #[async_handler]
+    impl StreamHandler<u64> for AnActor {
+        async fn handle(&mut self, item: u64, _ctx: &mut Self::Context) {
+            let result = self.delegate.send(Count(item)).await;
+            self.log.lock().unwrap().push(result.unwrap());
+        }
+
+        async fn finished(&mut self, ctx: &mut Self::Context) {
+            let result = self.delegate.send(Count(0)).await;
+            self.log.lock().unwrap().push(result.unwrap());
+            ctx.stop();
+        }
+    }
+
+    let log = Arc::new(Mutex::new(vec![]));
+    let addr = AnActor { delegate: Counter {}.start(), log: log.clone() }.start();
+    while addr.connected() {
+        actix_rt::task::yield_now().await;
+    }
+    assert_eq!(vec![2, 3, 4, 1], *log.lock().unwrap());
+}
+
+#[actix_rt::test]
+async fn test_async_restarting() {
+    use actix::{ActorContext, Supervised, Supervisor};
+
+    #[derive(Message)]
+    #[rtype(result = "()")]
+    struct Die;
+
+    #[derive(Message)]
+    #[rtype(result = "(u64, u64)")]
+    struct Restored;
+
+    struct AnActor {
+        delegate: Addr<Counter>,
+        restarts: u64,
+        restored: u64
+    }
+
+    impl Actor for AnActor {
+        type Context = Context<Self>;
+    }
+
+    #[async_actor]
+    impl Supervised for AnActor {
+        async fn restarting(&mut self, _ctx: &mut Self::Context) {
+            self.restarts += 1;
+            let result = self.delegate.send(Count(self.restarts * 10)).await;
+            self.restored = result.unwrap();
+        }
+    }
+
+    impl Handler<Die> for AnActor {
+        type Result = ();
+
+        fn handle(&mut self, _msg: Die, ctx: &mut Self::Context) {
+            ctx.stop();
+        }
+    }
+
+    #[async_handler]
+    impl Handler<Restored> for AnActor {
+        fn handle(&mut self, _msg: Restored, _ctx: &mut Self::Context) -> (u64, u64) {
+            (self.restarts, self.restored)
+        }
+    }
+
+    let delegate = Counter {}.start();
+    let addr = Supervisor::start(move |_| AnActor { delegate, restarts: 0, restored: 0 });
+    assert_eq!((0, 0), addr.send(Restored).await.unwrap());
+    addr.do_send(Die);
+    assert_eq!((1, 11), addr.send(Restored).await.unwrap());
+    addr.do_send(Die);
+    assert_eq!((2, 21), addr.send(Restored).await.unwrap());
+}
+
+#[actix_rt::test]
+async fn test_actor_async() {
+    use actix::AsyncContext;
+
+    #[derive(Message)]
+    #[rtype(result = "()")]
+    struct Refresh(u64);
+
+    #[derive(Message)]
+    #[rtype(result = "u64")]
+    struct Total;
+
+    struct AnActor {
+        delegate: Addr<Counter>,
+        total: u64
+    }
+
+    impl Actor for AnActor {
+        type Context = Context<Self>;
+
+        fn started(&mut self, ctx: &mut Self::Context) {
+            ctx.wait(actor_async!(|act, _| {
+                let result = act.delegate.send(Count(99)).await;
+                act.total = result.unwrap();
+            }));
+        }
+    }
+
+    impl Handler<Refresh> for AnActor {
+        type Result = ();
+
+        fn handle(&mut self, msg: Refresh, ctx: &mut Self::Context) {
+            ctx.wait(actor_async!(self, ctx, {
+                let result = self.delegate.send(Count(msg.0)).await;
+                self.total += result.unwrap();
+                // refreshes once more through the mailbox, after the messages already in there
+                if msg.0 != 0 {
+                    ctx.address().do_send(Refresh(0));
+                }
+            }));
+        }
+    }
+
+    impl Handler<Total> for AnActor {
+        type Result = u64;
+
+        fn handle(&mut self, _msg: Total, _ctx: &mut Self::Context) -> Self::Result {
+            self.total
+        }
+    }
+
+    let addr = AnActor { delegate: Counter {}.start(), total: 0 }.start();
+    assert_eq!(100, addr.send(Total).await.unwrap());
+    addr.do_send(Refresh(9));
+    assert_eq!(110, addr.send(Total).await.unwrap());
+    assert_eq!(111, addr.send(Total).await.unwrap());
+}